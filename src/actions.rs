@@ -1,14 +1,51 @@
+use crate::components::Direction;
 use crate::components::GridPosition;
 use crate::ui::DialogBoxConf;
+use serde::Deserialize;
+use std::collections::HashSet;
 
-#[derive(Debug, Clone)]
+// Actions can also be loaded from data files (e.g. dialog trees), using serde's default enum representation:
+// { "Teleport": { "x": 10, "y": 11 } } or { "PrintMessage": "hello" }
+#[derive(Debug, Clone, Deserialize)]
 pub enum Action {
     // Teleports player to a certain location
     Teleport(GridPosition),
-    // shows a message with a basic dialog box
+    // Moves player to another elevation of the current level, e.g. when climbing stairs or a ladder
+    ChangeElevation(usize),
+    // Moves player to a location in another level (see assets/levels), unloading the current level
+    ChangeLevel {
+        level_id: String,
+        position: GridPosition,
+        facing: Direction,
+    },
+    // shows a message (string id, see localization.rs) with a basic dialog box
     ShowSimpleDialog(String),
     // shows a full dialog box, including optional character name and portrait
     ShowDialog(DialogBoxConf),
+    // starts a conversation from a dialog tree, at the given node id
+    StartConversation(String),
+    // Sends a character (by PersistentId, e.g. "small_room/7" or "player") walking to a location in the current level,
+    // optionally turning to face a direction once there. handy for cutscenes.
+    WalkTo {
+        id: String,
+        position: GridPosition,
+        #[serde(default)]
+        facing: Option<Direction>,
+    },
     // Prints a message (used for debugging or testing triggers)
     PrintMessage(String),
 }
+
+// global queue of actions that weren't caused by a trigger (e.g. chosen during a conversation)
+// these are processed by the ActionSystem along with any triggered actions
+#[derive(Default)]
+pub struct ActionQueue {
+    pub actions: Vec<Action>,
+}
+
+// global record of the TriggerOnce triggers that have fired, by the PersistentId of their entity.
+// it outlives the level, so those triggers stay used up when the player comes back, and it's kept in save files.
+#[derive(Default)]
+pub struct FiredTriggers {
+    pub ids: HashSet<String>,
+}
@@ -0,0 +1,410 @@
+use crate::constants::CONTROLS_FILE;
+use crate::constants::DEFAULT_CONTROLS_FILE;
+use crate::gamepad::GamepadProfile;
+use crate::gamepad::GamepadProfiles;
+use crate::platform::key_from_name;
+use crate::platform::key_name;
+use crate::platform::Input;
+use macroquad::prelude::*;
+use serde::Deserialize;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+
+// Systems don't check for particular keys, they check for these actions, and the KeyBindings resource says which keys (and gamepad buttons) do what.
+// The default bindings are in assets/controls.json, and any the player changes in the controls menu are saved to CONTROLS_FILE.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum InputAction {
+    MoveUp,
+    MoveDown,
+    MoveLeft,
+    MoveRight,
+    Use, // also confirms in menus and dialogs
+    Cancel,
+    Menu, // opens the controls menu
+    Language,
+    Save,
+    Load,
+    SaveSlot1,
+    SaveSlot2,
+    SaveSlot3,
+}
+
+impl InputAction {
+    // in the order they're listed in the controls menu
+    pub const ALL: [InputAction; 13] = [
+        InputAction::MoveUp,
+        InputAction::MoveDown,
+        InputAction::MoveLeft,
+        InputAction::MoveRight,
+        InputAction::Use,
+        InputAction::Cancel,
+        InputAction::Menu,
+        InputAction::Language,
+        InputAction::Save,
+        InputAction::Load,
+        InputAction::SaveSlot1,
+        InputAction::SaveSlot2,
+        InputAction::SaveSlot3,
+    ];
+
+    // string id of the action's name in the controls menu
+    pub fn string_id(&self) -> &'static str {
+        match self {
+            InputAction::MoveUp => "controls.move_up",
+            InputAction::MoveDown => "controls.move_down",
+            InputAction::MoveLeft => "controls.move_left",
+            InputAction::MoveRight => "controls.move_right",
+            InputAction::Use => "controls.use",
+            InputAction::Cancel => "controls.cancel",
+            InputAction::Menu => "controls.menu",
+            InputAction::Language => "controls.language",
+            InputAction::Save => "controls.save",
+            InputAction::Load => "controls.load",
+            InputAction::SaveSlot1 => "controls.save_slot_1",
+            InputAction::SaveSlot2 => "controls.save_slot_2",
+            InputAction::SaveSlot3 => "controls.save_slot_3",
+        }
+    }
+}
+
+// how bindings are written in the controls files, e.g. { "keys": { "MoveUp": ["W", "Up"] }, "gamepad_profile": "standard" }.
+// keys are sorted so saving gives a stable file.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+#[serde(default)]
+pub struct BindingsFile {
+    pub keys: BTreeMap<InputAction, Vec<String>>,
+    pub gamepad_profile: Option<String>, // name of one of the profiles in GAMEPAD_PROFILES_FILE
+}
+
+// global resource with the bindings from DEFAULT_CONTROLS_FILE, which the controls menu can reset to
+pub struct DefaultKeyBindings {
+    pub bindings: KeyBindings,
+}
+
+// global resource with the keys bound to each action, and the gamepad profile saying which buttons do what
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct KeyBindings {
+    keys: HashMap<InputAction, Vec<KeyCode>>,
+    pub gamepad: GamepadProfile,
+}
+
+impl KeyBindings {
+    // the defaults, with the player's own bindings (if they've changed any) on top
+    pub async fn load(profiles: &GamepadProfiles) -> KeyBindings {
+        let mut bindings = KeyBindings::load_defaults(profiles).await;
+        // the file only exists once the player has changed a control (and never in the browser)
+        if let Ok(json) = std::fs::read_to_string(CONTROLS_FILE) {
+            let player_file =
+                serde_json::from_str::<BindingsFile>(&json).map_err(|err| err.to_string());
+            let player_bindings = player_file.and_then(|file| {
+                let gamepad = match &file.gamepad_profile {
+                    Some(name) => find_profile(profiles, name),
+                    None => bindings.gamepad.clone(),
+                };
+                KeyBindings::from_file(&file, gamepad)
+            });
+            match player_bindings {
+                Ok(player_bindings) => {
+                    bindings.keys.extend(player_bindings.keys);
+                    bindings.gamepad = player_bindings.gamepad;
+                }
+                Err(err) => println!(
+                    "WARNING: ignoring {}, using default controls: {}",
+                    CONTROLS_FILE, err
+                ),
+            }
+        }
+        bindings
+    }
+
+    pub async fn load_defaults(profiles: &GamepadProfiles) -> KeyBindings {
+        let json_bytes = load_file(DEFAULT_CONTROLS_FILE)
+            .await
+            .unwrap_or_else(|_| panic!("failed to load {}", DEFAULT_CONTROLS_FILE));
+        let file: BindingsFile = serde_json::from_slice(&json_bytes)
+            .unwrap_or_else(|err| panic!("failed to parse {}: {}", DEFAULT_CONTROLS_FILE, err));
+        let gamepad = match &file.gamepad_profile {
+            Some(name) => find_profile(profiles, name),
+            None => profiles.profiles[0].clone(),
+        };
+        let bindings = KeyBindings::from_file(&file, gamepad)
+            .unwrap_or_else(|err| panic!("{}: {}", DEFAULT_CONTROLS_FILE, err));
+        for action in InputAction::ALL.iter() {
+            if bindings.keys(*action).is_empty() {
+                println!(
+                    "WARNING: {:?} has no keys in {}",
+                    action, DEFAULT_CONTROLS_FILE
+                );
+            }
+        }
+        bindings
+    }
+
+    // the gamepad profile is passed in, rather than looked up by the name in the file, since input recordings keep the whole profile
+    pub fn from_file(file: &BindingsFile, gamepad: GamepadProfile) -> Result<KeyBindings, String> {
+        let mut keys = HashMap::new();
+        for (action, names) in file.keys.iter() {
+            let action_keys = names
+                .iter()
+                .map(|name| {
+                    key_from_name(name)
+                        .ok_or_else(|| format!("unknown key {} for {:?}", name, action))
+                })
+                .collect::<Result<Vec<KeyCode>, String>>()?;
+            keys.insert(*action, action_keys);
+        }
+        Ok(KeyBindings { keys, gamepad })
+    }
+
+    pub fn to_file(&self) -> BindingsFile {
+        let keys = self
+            .keys
+            .iter()
+            .map(|(action, keys)| {
+                (
+                    *action,
+                    keys.iter().map(|key| key_name(*key).to_owned()).collect(),
+                )
+            })
+            .collect();
+        BindingsFile {
+            keys,
+            gamepad_profile: Some(self.gamepad.name.to_owned()),
+        }
+    }
+
+    // writes every binding to CONTROLS_FILE, so the player's changes are kept for next time
+    pub fn save(&self) -> Result<(), String> {
+        let json = serde_json::to_string_pretty(&self.to_file()).map_err(|err| err.to_string())?;
+        std::fs::write(CONTROLS_FILE, json).map_err(|err| err.to_string())
+    }
+
+    pub fn keys(&self, action: InputAction) -> &[KeyCode] {
+        self.keys.get(&action).map_or(&[], |keys| keys.as_slice())
+    }
+
+    // the keys bound to the action, for showing to the player, e.g. "W/Up"
+    pub fn describe(&self, action: InputAction) -> String {
+        let names: Vec<&str> = self.keys(action).iter().map(|key| key_name(*key)).collect();
+        if names.is_empty() {
+            return "-".to_owned();
+        }
+        names.join("/")
+    }
+
+    // the gamepad buttons the profile binds to the action, e.g. "DPadUp/StickUp"
+    pub fn describe_buttons(&self, action: InputAction) -> String {
+        let names: Vec<String> = self
+            .gamepad
+            .buttons(action)
+            .iter()
+            .map(|button| format!("{:?}", button))
+            .collect();
+        if names.is_empty() {
+            return "-".to_owned();
+        }
+        names.join("/")
+    }
+
+    pub fn is_down(&self, input: &Input, action: InputAction) -> bool {
+        self.keys(action).iter().any(|key| input.is_key_down(*key))
+            || self
+                .gamepad
+                .buttons(action)
+                .iter()
+                .any(|button| input.is_button_down(*button))
+    }
+
+    pub fn is_pressed(&self, input: &Input, action: InputAction) -> bool {
+        self.keys(action)
+            .iter()
+            .any(|key| input.is_key_pressed(*key))
+            || self
+                .gamepad
+                .buttons(action)
+                .iter()
+                .any(|button| input.is_button_pressed(*button))
+    }
+
+    // makes the key the only one bound to the action. it's taken away from any other action, so one key never does two things.
+    // an action left with no keys at all gets the action's old keys instead, so the two swap and nothing becomes unreachable.
+    pub fn rebind(&mut self, action: InputAction, key: KeyCode) {
+        let old_keys: Vec<KeyCode> = self
+            .keys(action)
+            .iter()
+            .copied()
+            .filter(|old_key| *old_key != key)
+            .collect();
+        for (other_action, keys) in self.keys.iter_mut() {
+            if *other_action == action || !keys.contains(&key) {
+                continue;
+            }
+            keys.retain(|bound_key| *bound_key != key);
+            if keys.is_empty() {
+                *keys = old_keys.clone();
+            }
+        }
+        self.keys.insert(action, vec![key]);
+    }
+}
+
+// the named profile, or the first one if there's no such profile (e.g. it was renamed since the player picked it)
+fn find_profile(profiles: &GamepadProfiles, name: &str) -> GamepadProfile {
+    match profiles.get(name) {
+        Some(profile) => profile.clone(),
+        None => {
+            println!(
+                "WARNING: no gamepad profile {}, using {}",
+                name, profiles.profiles[0].name
+            );
+            profiles.profiles[0].clone()
+        }
+    }
+}
+
+// Turns holding a direction into repeated presses, for moving the cursor through menus and dialog choices.
+// The first repeat comes after the gamepad profile's repeat_delay, then every repeat_interval after that.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct MenuRepeat {
+    held: Option<InputAction>,
+    until_repeat: f32, // seconds
+}
+
+impl MenuRepeat {
+    // true on the frame the action is pressed, and on every repeat while it's held
+    pub fn is_triggered(
+        &mut self,
+        bindings: &KeyBindings,
+        input: &Input,
+        action: InputAction,
+        delta_seconds: f32,
+    ) -> bool {
+        if bindings.is_pressed(input, action) {
+            self.held = Some(action);
+            self.until_repeat = bindings.gamepad.repeat_delay;
+            return true;
+        }
+        if self.held != Some(action) {
+            return false;
+        }
+        if !bindings.is_down(input, action) {
+            self.held = None;
+            return false;
+        }
+        self.until_repeat -= delta_seconds;
+        if self.until_repeat > 0. {
+            return false;
+        }
+        self.until_repeat += bindings.gamepad.repeat_interval;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::gamepad::GamepadButton;
+
+    fn bindings() -> KeyBindings {
+        let file: BindingsFile = serde_json::from_str(
+            r#"{ "keys": { "MoveUp": ["W", "Up"], "Use": ["Space"], "Cancel": ["Escape"] } }"#,
+        )
+        .unwrap();
+        let gamepad = serde_json::from_str(
+            r#"{ "name": "test", "dead_zone": 0.3, "repeat_delay": 0.5, "repeat_interval": 0.25, "buttons": { "MoveUp": ["DPadUp", "StickUp"], "Use": ["South"] } }"#,
+        )
+        .unwrap();
+        KeyBindings::from_file(&file, gamepad).unwrap()
+    }
+
+    #[test]
+    fn test_actions() {
+        let bindings = bindings();
+        let input = Input::default().hold(KeyCode::Up).press(KeyCode::Space);
+        assert!(bindings.is_down(&input, InputAction::MoveUp));
+        assert!(!bindings.is_pressed(&input, InputAction::MoveUp));
+        assert!(bindings.is_pressed(&input, InputAction::Use));
+        assert!(!bindings.is_down(&input, InputAction::Cancel));
+        // nothing bound at all
+        assert!(!bindings.is_down(&input, InputAction::Load));
+        assert_eq!(bindings.describe(InputAction::MoveUp), "W/Up");
+        assert_eq!(bindings.describe(InputAction::Load), "-");
+        assert_eq!(
+            bindings.describe_buttons(InputAction::MoveUp),
+            "DPadUp/StickUp"
+        );
+    }
+
+    #[test]
+    fn test_gamepad_actions() {
+        let bindings = bindings();
+        let input = Input::default()
+            .hold_button(GamepadButton::StickUp)
+            .press_button(GamepadButton::South);
+        assert!(bindings.is_down(&input, InputAction::MoveUp));
+        assert!(!bindings.is_pressed(&input, InputAction::MoveUp));
+        assert!(bindings.is_pressed(&input, InputAction::Use));
+        assert!(!bindings.is_down(&input, InputAction::Cancel));
+    }
+
+    #[test]
+    fn test_menu_repeat() {
+        let bindings = bindings();
+        let mut repeat = MenuRepeat {
+            ..Default::default()
+        };
+        let mut triggered = |input: Input| {
+            (0..10)
+                .filter(|_| repeat.is_triggered(&bindings, &input, InputAction::MoveUp, 0.125))
+                .count()
+        };
+        assert_eq!(
+            triggered(Input::default().press_button(GamepadButton::DPadUp)),
+            10
+        );
+        // held for 1.25 seconds: the first repeat comes after 0.5 seconds, then one every 0.25 seconds
+        assert_eq!(triggered(Input::default().hold(KeyCode::Up)), 4);
+        assert_eq!(triggered(Input::default()), 0);
+        assert_eq!(triggered(Input::default().hold(KeyCode::Up)), 0);
+    }
+
+    #[test]
+    fn test_rebind() {
+        let mut bindings = bindings();
+        bindings.rebind(InputAction::Use, KeyCode::W);
+        assert_eq!(bindings.keys(InputAction::Use), &[KeyCode::W]);
+        assert_eq!(bindings.keys(InputAction::MoveUp), &[KeyCode::Up]);
+        // taking an action's only key swaps them over
+        bindings.rebind(InputAction::Cancel, KeyCode::W);
+        assert_eq!(bindings.keys(InputAction::Cancel), &[KeyCode::W]);
+        assert_eq!(bindings.keys(InputAction::Use), &[KeyCode::Escape]);
+
+        // what gets saved loads back the same
+        let file = bindings.to_file();
+        assert_eq!(file.keys[&InputAction::Use], vec!["Escape".to_owned()]);
+        assert_eq!(file.gamepad_profile, Some("test".to_owned()));
+        assert_eq!(
+            KeyBindings::from_file(&file, bindings.gamepad.clone()).unwrap(),
+            bindings
+        );
+    }
+
+    #[test]
+    fn test_bad_bindings() {
+        let file: BindingsFile =
+            serde_json::from_str(r#"{ "keys": { "Use": ["Hyper"] } }"#).unwrap();
+        assert_eq!(
+            KeyBindings::from_file(&file, GamepadProfile::default()),
+            Err("unknown key Hyper for Use".to_owned())
+        );
+        assert!(
+            serde_json::from_str::<BindingsFile>(r#"{ "keys": { "Jump": ["Space"] } }"#).is_err()
+        );
+        assert!(serde_json::from_str::<GamepadProfile>(
+            r#"{ "name": "test", "dead_zone": 0.3, "repeat_delay": 0.4, "repeat_interval": 0.1, "buttons": { "Use": ["Z"] } }"#
+        )
+        .is_err());
+    }
+}
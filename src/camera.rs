@@ -0,0 +1,91 @@
+use crate::constants::GLOBAL_MULTIPLIER;
+use crate::constants::GLOBAL_OFFSET_X;
+use crate::constants::GLOBAL_OFFSET_Y;
+
+// global resource describing which part of the map is on screen.
+// everything is in grid (tile) coordinates, so it works with GridPosition directly.
+#[derive(Default)]
+pub struct Camera {
+    pub x: f32, // top-left corner of the view
+    pub y: f32,
+    pub view_width: f32, // how many tiles fit on screen
+    pub view_height: f32,
+}
+
+impl Camera {
+    // centers the view on the target position, without showing anything beyond the edges of the map.
+    // maps smaller than the view are simply drawn from the top-left corner.
+    pub fn follow(&mut self, target_x: f32, target_y: f32, map_width: f32, map_height: f32) {
+        self.x = clamp_axis(
+            target_x + 0.5 - self.view_width / 2.,
+            self.view_width,
+            map_width,
+        );
+        self.y = clamp_axis(
+            target_y + 0.5 - self.view_height / 2.,
+            self.view_height,
+            map_height,
+        );
+    }
+
+    // range of tile columns that are at least partially visible, clamped to the map
+    pub fn visible_columns(&self, map_width: f32) -> std::ops::Range<u32> {
+        visible_range(self.x, self.view_width, map_width)
+    }
+
+    // range of tile rows that are at least partially visible, clamped to the map
+    pub fn visible_rows(&self, map_height: f32) -> std::ops::Range<u32> {
+        visible_range(self.y, self.view_height, map_height)
+    }
+
+    // converts a grid position to the screen position of its top-left corner
+    pub fn grid_to_screen(
+        &self,
+        grid_x: f32,
+        grid_y: f32,
+        tile_width: f32,
+        tile_height: f32,
+    ) -> (f32, f32) {
+        (
+            GLOBAL_OFFSET_X + (grid_x - self.x) * tile_width * GLOBAL_MULTIPLIER,
+            GLOBAL_OFFSET_Y + (grid_y - self.y) * tile_height * GLOBAL_MULTIPLIER,
+        )
+    }
+
+    // the grid tile at a screen position, e.g. where the player clicked, or None if that's outside the view
+    pub fn screen_to_grid(
+        &self,
+        screen_x: f32,
+        screen_y: f32,
+        tile_width: f32,
+        tile_height: f32,
+    ) -> Option<(f32, f32)> {
+        let view_x = (screen_x - GLOBAL_OFFSET_X) / (tile_width * GLOBAL_MULTIPLIER);
+        let view_y = (screen_y - GLOBAL_OFFSET_Y) / (tile_height * GLOBAL_MULTIPLIER);
+        if view_x < 0. || view_y < 0. || view_x >= self.view_width || view_y >= self.view_height {
+            return None;
+        }
+        Some(((self.x + view_x).floor(), (self.y + view_y).floor()))
+    }
+
+    // whether anything at the given grid position could be on screen (with a tile of leeway for tall sprites)
+    pub fn is_visible(&self, grid_x: f32, grid_y: f32) -> bool {
+        grid_x > self.x - 1.
+            && grid_x < self.x + self.view_width
+            && grid_y > self.y - 1.
+            && grid_y < self.y + self.view_height + 1.
+    }
+}
+
+fn clamp_axis(start: f32, view_size: f32, map_size: f32) -> f32 {
+    if map_size <= view_size {
+        return 0.;
+    }
+    start.max(0.).min(map_size - view_size)
+}
+
+fn visible_range(start: f32, view_size: f32, map_size: f32) -> std::ops::Range<u32> {
+    let first = start.floor().max(0.) as u32;
+    let last = (start + view_size).ceil().min(map_size).max(0.) as u32;
+    first..last
+}
@@ -0,0 +1,43 @@
+use crate::constants::GAME_CLOCK_START_HOUR;
+use crate::constants::GAME_MINUTES_PER_SECOND;
+
+// global resource with the time of day in the game, which NPC schedules (see Patrol) follow.
+// it's moved forward by the ClockSystem every frame, wraps around at midnight, and is kept in save files.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GameClock {
+    pub minutes: f32, // since midnight
+}
+
+impl Default for GameClock {
+    fn default() -> GameClock {
+        GameClock {
+            minutes: GAME_CLOCK_START_HOUR * 60.,
+        }
+    }
+}
+
+impl GameClock {
+    pub fn advance(&mut self, delta_seconds: f32) {
+        self.minutes = (self.minutes + delta_seconds * GAME_MINUTES_PER_SECOND) % (24. * 60.);
+    }
+
+    // e.g. 13.5 for half past one in the afternoon
+    pub fn hour(&self) -> f32 {
+        self.minutes / 60.
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_clock() {
+        let mut clock = GameClock { minutes: 23. * 60. };
+        assert_eq!(clock.hour(), 23.);
+        clock.advance(90. / GAME_MINUTES_PER_SECOND);
+        assert_eq!(clock.hour(), 0.5);
+        assert_eq!(GameClock::default().hour(), GAME_CLOCK_START_HOUR);
+    }
+}
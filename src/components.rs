@@ -1,6 +1,8 @@
 use crate::actions::Action;
 use crate::constants::*;
 use macroquad::texture::Texture2D;
+use serde::Deserialize;
+use serde::Serialize;
 use specs::Entity;
 use specs::{Component, DenseVecStorage};
 
@@ -17,7 +19,7 @@ pub struct PlayerEntity {
 }
 
 // GridPosition is used to place entities on the map, using grid coordinates
-#[derive(Component, Copy, Clone, Debug, PartialEq)]
+#[derive(Component, Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct GridPosition {
     pub x: f32,
     pub y: f32,
@@ -37,15 +39,96 @@ pub struct FacingDirection {
     pub direction: Direction,
 }
 
-// a strolling entity will pause for some amount of time, then move in a random direction, and repeat
+// a strolling entity will pause for some amount of time, then move in a random direction, and repeat.
+// it only steps onto tiles inside its area (if it has one), and never onto excluded tiles or triggers (e.g. doors).
 #[derive(Component)]
 pub struct Strolling {
     pub max_pause_seconds: f32,
+    pub area: Option<StrollArea>,
+    pub exclude: Vec<GridPosition>,
+}
+
+// the part of the map a strolling entity stays in, in grid coordinates
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum StrollArea {
+    Rect {
+        x: f32,
+        y: f32,
+        width: f32,
+        height: f32,
+    },
+    Circle {
+        x: f32,
+        y: f32,
+        radius: f32,
+    },
+}
+
+impl StrollArea {
+    pub fn contains(&self, position: &GridPosition) -> bool {
+        match *self {
+            StrollArea::Rect {
+                x,
+                y,
+                width,
+                height,
+            } => {
+                position.x >= x
+                    && position.y >= y
+                    && position.x < x + width
+                    && position.y < y + height
+            }
+            StrollArea::Circle { x, y, radius } => {
+                (position.x - x).powi(2) + (position.y - y).powi(2) <= radius.powi(2)
+            }
+        }
+    }
+
+    pub fn center(&self) -> GridPosition {
+        match *self {
+            StrollArea::Rect {
+                x,
+                y,
+                width,
+                height,
+            } => GridPosition {
+                x: x + (width - 1.) / 2.,
+                y: y + (height - 1.) / 2.,
+            },
+            StrollArea::Circle { x, y, .. } => GridPosition { x, y },
+        }
+    }
 }
 
 #[derive(Component)]
 pub struct BlocksMovement;
 
+// which of the level's elevations (see level.rs) an entity is on, e.g. the floor or the top of a platform.
+// it decides which collision layer the entity moves on, and which triggers and other entities it can interact with.
+// entities without this component are on the ground (elevation 0).
+#[derive(Component, Copy, Clone, Debug, PartialEq, Default)]
+pub struct Elevation {
+    pub index: usize,
+}
+
+impl Elevation {
+    pub fn index_of(elevation: Option<&Elevation>) -> usize {
+        elevation.map_or(0, |elevation| elevation.index)
+    }
+}
+
+// id that identifies an entity across runs of the game, unlike specs' Entity which depends on creation order.
+// used to match up entities with their state in save files, and to remember which triggers have fired.
+#[derive(Component, Clone, Debug, PartialEq)]
+pub struct PersistentId {
+    pub id: String,
+}
+
+// "marker" for entities that belong to the currently loaded level, which get deleted when the player changes levels
+#[derive(Component)]
+pub struct LevelEntity;
+
 //////////////////////////////////////////
 //
 // Components used with Actions system
@@ -67,6 +150,17 @@ pub struct TriggerActionOnUse {
     pub action: Action,
 }
 
+// "marker" for triggers that only fire the first time, see FiredTriggers
+#[derive(Component)]
+pub struct TriggerOnce;
+
+// actions are indexed by choice, i.e. actions[1] runs when the player picks the second choice of the dialog
+#[derive(Component)]
+pub struct TriggerActionOnDialogChoice {
+    pub dialog_id: u32,
+    pub actions: Vec<Action>,
+}
+
 //////////////////////////////////////////
 //
 // Components used as States (i.e. state machine)
@@ -81,6 +175,73 @@ pub struct WaitingState {
     pub remaining_wait_seconds: f32,
 }
 
+// a path an entity is walking, one tile per step: the player's after clicking (or tapping) on the map, or anyone's
+// sent somewhere by Action::WalkTo. see FollowPathSystem.
+#[derive(Component, Debug, Clone, PartialEq)]
+pub struct WalkingPath {
+    pub steps: Vec<GridPosition>, // tiles still to step onto, in reverse, so the next one is last. planned on the way.
+    pub destination: GridPosition,
+    pub use_destination: bool, // walk up to the destination and use it, rather than onto it (only the player uses things)
+    pub facing: Option<Direction>, // which way to turn once there
+    pub blocked_seconds: f32,  // how long the way has been blocked with no way around
+    pub from_click: bool, // the player clicked there, so their keys or a dialog can cancel it. a scripted walk carries on.
+}
+
+impl WalkingPath {
+    pub fn to(destination: GridPosition) -> WalkingPath {
+        WalkingPath {
+            steps: vec![],
+            destination,
+            use_destination: false,
+            facing: None,
+            blocked_seconds: 0.,
+            from_click: false,
+        }
+    }
+}
+
+// an NPC that walks a loop of waypoints (e.g. a guard, or a shopkeeper going between the counter and a shelf), waiting at
+// each one for a while. with a schedule, the route changes with the time of day. see PatrolSystem.
+#[derive(Component, Debug, Clone, PartialEq)]
+pub struct Patrol {
+    pub route: Vec<Waypoint>,
+    pub schedule: Vec<ScheduleEntry>, // in order of start time. empty means the route never changes.
+    pub next_waypoint: usize,
+    pub remaining_wait_seconds: f32,
+    pub walking: bool, // whether it was sent along a WalkingPath to the next waypoint
+}
+
+impl Patrol {
+    // the route for the given hour of the day: the entry that started last, or before the first one, yesterday's last
+    pub fn scheduled_route(&self, hour: f32) -> Option<&Vec<Waypoint>> {
+        self.schedule
+            .iter()
+            .rev()
+            .find(|entry| entry.start_hour <= hour)
+            .or_else(|| self.schedule.last())
+            .map(|entry| &entry.route)
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Waypoint {
+    pub x: f32,
+    pub y: f32,
+    #[serde(default)]
+    pub wait_seconds: f32,
+    #[serde(default)]
+    pub facing: Option<Direction>, // which way to face while waiting there
+}
+
+// a route that starts at some hour of the day. a single waypoint sends the NPC somewhere to stay, e.g. home at night.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct ScheduleEntry {
+    pub start_hour: f32,
+    pub route: Vec<Waypoint>,
+}
+
 #[derive(Component)]
 pub struct EntityMovingState {
     pub delta_x: f32,
@@ -94,7 +255,7 @@ pub struct EntityMovingState {
 //
 //////////////////////////////////////////
 
-#[derive(PartialEq, Copy, Clone, Debug)]
+#[derive(PartialEq, Copy, Clone, Debug, Serialize, Deserialize)]
 pub enum Direction {
     Up,
     Left,
@@ -2,11 +2,23 @@ pub const GLOBAL_MULTIPLIER: f32 = 2.; // Global multiplier is the 2x scale we u
 pub const GLOBAL_OFFSET_X: f32 = 50.;
 pub const GLOBAL_OFFSET_Y: f32 = 50.;
 pub const FONT_SIZE: u32 = 24;
+// characters always included in the font atlas (printable ASCII and Latin-1), on top of any found in the string tables
+pub const FONT_CHARACTER_RANGES: &[(char, char)] = &[(' ', '~'), ('\u{a1}', '\u{ff}')];
 
-pub const LEVEL_WIDTH: f32 = 14.;
-pub const LEVEL_HEIGHT: f32 = 14.;
+// level id (see assets/levels) loaded when the game starts
+pub const STARTING_LEVEL: &str = "small_room";
+
+// how many tiles of the map are shown on screen at once (the camera scrolls around larger maps)
+pub const VIEWPORT_WIDTH: f32 = 21.;
+pub const VIEWPORT_HEIGHT: f32 = 21.;
 
 pub const CHARACTER_MOVEMENT_DURATION: f32 = 0.4; // this should be evenly divisible by 4 because we have 4 frames in the movement animation
+pub const WALKING_PATH_PATIENCE_SECONDS: f32 = 2.; // how long a clicked path waits for something in the way to move, before giving up
+pub const PATROL_RETRY_SECONDS: f32 = 1.; // how long a patrolling NPC that gave up on reaching a waypoint waits before trying again
+
+// the game's time of day, see clock.rs. at one game minute per second, a day lasts 24 minutes.
+pub const GAME_MINUTES_PER_SECOND: f32 = 1.;
+pub const GAME_CLOCK_START_HOUR: f32 = 8.;
 
 // these are the sprite frames (aka columns in the sprite sheet) that show player facing different directions
 pub const CHARACTER_DOWN_FACING_FRAME: f32 = 8.;
@@ -16,7 +28,42 @@ pub const CHARACTER_LEFT_FACING_FRAME: f32 = 12.;
 
 // these are the raw texture IDs used by MegaUI
 pub const UI_TEXTURE_CARET: u32 = 1000;
+pub const UI_TEXTURE_PORTRAIT: u32 = 1001; // only one dialog box is open at a time, so they can all share this id
 
 // Dialog box constants
 pub const DIALOG_BOX_WIDTH: f32 = 780.;
-pub const DIALOG_BOX_HEIGHT: f32 = 120.;
\ No newline at end of file
+pub const DIALOG_BOX_HEIGHT: f32 = 120.;
+pub const DIALOG_TEXT_MARGIN: f32 = 10.; // space between text and the box edge (or portrait)
+pub const DIALOG_PORTRAIT_SIZE: f32 = 64.;
+pub const DIALOG_CHARS_PER_SECOND: f32 = 40.; // default speed of the typewriter-style text reveal
+pub const DIALOG_EMPHASIS_COLOR: &str = "gold"; // color name (see markup.rs) used for [em] tags
+pub const DEFAULT_PLAYER_NAME: &str = "Rob";
+pub const DIALOG_CHOICE_HEIGHT: f32 = 28.; // extra height added to dialog box for each choice shown
+
+// Controls menu constants
+pub const CONTROLS_MENU_WIDTH: f32 = 640.;
+pub const CONTROLS_MENU_ROW_HEIGHT: f32 = 28.;
+pub const CONTROLS_MENU_KEYS_X: f32 = 260.; // where the column of bound keys starts
+pub const CONTROLS_MENU_BUTTONS_X: f32 = 420.; // and the column of gamepad buttons
+
+// Languages with string tables in assets/strings, the first one is the default
+pub const LANGUAGES: &[&str] = &["en", "fr"];
+
+// Dialog tree files, loaded at startup
+pub const DIALOG_TREE_FILES: &[&str] = &["assets/dialogs/villager.json"];
+
+// component bundles for the player and NPCs, see prefabs.rs
+pub const PREFAB_FILE: &str = "assets/prefabs.json";
+
+// save files, see save.rs. bump SAVE_VERSION whenever the save format changes
+pub const SAVE_VERSION: u32 = 2;
+pub const SAVE_DIRECTORY: &str = "saves";
+pub const SAVE_SLOTS: usize = 3;
+
+// controls, see bindings.rs. the defaults are in the assets, and the player's own bindings are saved next to the game
+pub const DEFAULT_CONTROLS_FILE: &str = "assets/controls.json";
+pub const CONTROLS_FILE: &str = "controls.json";
+pub const GAMEPAD_PROFILES_FILE: &str = "assets/gamepad_profiles.json";
+
+// input recordings, see recording.rs. bump this whenever the recording format changes
+pub const RECORDING_VERSION: u32 = 3;
@@ -0,0 +1,300 @@
+use crate::bindings::InputAction;
+use crate::bindings::KeyBindings;
+use crate::bindings::MenuRepeat;
+use crate::constants::CONTROLS_MENU_BUTTONS_X;
+use crate::constants::CONTROLS_MENU_KEYS_X;
+use crate::constants::CONTROLS_MENU_ROW_HEIGHT;
+use crate::constants::CONTROLS_MENU_WIDTH;
+use crate::gamepad::GamepadProfiles;
+use crate::localization::Localization;
+use crate::platform::Input;
+use macroquad::prelude::glam;
+use megaui_macroquad::draw_window;
+use megaui_macroquad::megaui::hash;
+use megaui_macroquad::megaui::widgets::Label;
+use megaui_macroquad::megaui::Vector2;
+use megaui_macroquad::WindowParams;
+
+// The in-game screen for rebinding controls. It lists every InputAction with the keys and gamepad buttons bound to it.
+// Pick an action and press Use, and the next key pressed becomes that action's key (Cancel gives up instead).
+// Gamepad buttons come from the gamepad profile, which is picked on the row after the actions. The last row resets everything to the defaults.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct ControlsMenu {
+    pub selected: usize, // index into InputAction::ALL, or one of the rows after them
+    pub rebinding: bool, // waiting for the key to bind to the selected action
+    repeat: MenuRepeat,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum MenuOutcome {
+    Open,
+    Changed, // a binding or the gamepad profile changed, so it's time to save them
+    Closed,
+}
+
+// the rows after all the actions
+const GAMEPAD_PROFILE_ROW: usize = InputAction::ALL.len();
+const RESET_ROW: usize = GAMEPAD_PROFILE_ROW + 1;
+
+impl ControlsMenu {
+    // handles a frame of input
+    pub fn update(
+        &mut self,
+        input: &Input,
+        delta_seconds: f32,
+        bindings: &mut KeyBindings,
+        defaults: &KeyBindings,
+        profiles: &GamepadProfiles,
+    ) -> MenuOutcome {
+        let row_count = RESET_ROW + 1;
+        if self.rebinding {
+            if bindings.is_pressed(input, InputAction::Cancel) {
+                self.rebinding = false;
+            } else if let Some(key) = input.first_pressed_key() {
+                bindings.rebind(InputAction::ALL[self.selected], key);
+                self.rebinding = false;
+                return MenuOutcome::Changed;
+            }
+            return MenuOutcome::Open;
+        }
+
+        if bindings.is_pressed(input, InputAction::Cancel)
+            || bindings.is_pressed(input, InputAction::Menu)
+        {
+            return MenuOutcome::Closed;
+        }
+        if self
+            .repeat
+            .is_triggered(bindings, input, InputAction::MoveUp, delta_seconds)
+        {
+            self.selected = (self.selected + row_count - 1) % row_count;
+        }
+        if self
+            .repeat
+            .is_triggered(bindings, input, InputAction::MoveDown, delta_seconds)
+        {
+            self.selected = (self.selected + 1) % row_count;
+        }
+        if bindings.is_pressed(input, InputAction::Use) {
+            if self.selected == GAMEPAD_PROFILE_ROW {
+                bindings.gamepad = profiles.next(&bindings.gamepad.name).clone();
+                return MenuOutcome::Changed;
+            }
+            if self.selected == RESET_ROW {
+                *bindings = defaults.clone();
+                return MenuOutcome::Changed;
+            }
+            self.rebinding = true;
+        }
+        MenuOutcome::Open
+    }
+
+    pub fn render(&self, bindings: &KeyBindings, localization: &Localization) {
+        // a row per action, the gamepad profile and reset rows, then a blank row and one for the help text
+        let height = (RESET_ROW + 4) as f32 * CONTROLS_MENU_ROW_HEIGHT;
+        draw_window(
+            hash!(),
+            glam::vec2(150., 100.),
+            glam::vec2(CONTROLS_MENU_WIDTH, height),
+            WindowParams {
+                label: localization.get("controls.title"),
+                movable: false,
+                titlebar: true,
+                ..Default::default()
+            },
+            |ui| {
+                for (index, action) in InputAction::ALL.iter().enumerate() {
+                    let y = index as f32 * CONTROLS_MENU_ROW_HEIGHT;
+                    let cursor = if index == self.selected { ">" } else { " " };
+                    Label::new(&format!(
+                        "{} {}",
+                        cursor,
+                        localization.get(action.string_id())
+                    ))
+                    .position(Some(Vector2::new(0., y)))
+                    .ui(ui);
+                    let keys = if self.rebinding && index == self.selected {
+                        localization.get("controls.press_key")
+                    } else {
+                        bindings.describe(*action)
+                    };
+                    Label::new(&keys)
+                        .position(Some(Vector2::new(CONTROLS_MENU_KEYS_X, y)))
+                        .ui(ui);
+                    Label::new(&bindings.describe_buttons(*action))
+                        .position(Some(Vector2::new(CONTROLS_MENU_BUTTONS_X, y)))
+                        .ui(ui);
+                }
+                let y = GAMEPAD_PROFILE_ROW as f32 * CONTROLS_MENU_ROW_HEIGHT;
+                let cursor = if self.selected == GAMEPAD_PROFILE_ROW {
+                    ">"
+                } else {
+                    " "
+                };
+                Label::new(&format!(
+                    "{} {}",
+                    cursor,
+                    localization.get("controls.gamepad_profile")
+                ))
+                .position(Some(Vector2::new(0., y)))
+                .ui(ui);
+                Label::new(&bindings.gamepad.name)
+                    .position(Some(Vector2::new(CONTROLS_MENU_KEYS_X, y)))
+                    .ui(ui);
+                let cursor = if self.selected == RESET_ROW { ">" } else { " " };
+                Label::new(&format!(
+                    "{} {}",
+                    cursor,
+                    localization.get("controls.reset")
+                ))
+                .position(Some(Vector2::new(
+                    0.,
+                    RESET_ROW as f32 * CONTROLS_MENU_ROW_HEIGHT,
+                )))
+                .ui(ui);
+                let help = format!(
+                    "{} - {}, {} - {}",
+                    bindings.describe(InputAction::Use),
+                    localization.get("controls.rebind"),
+                    bindings.describe(InputAction::Cancel),
+                    localization.get("controls.close")
+                );
+                Label::new(&help)
+                    .position(Some(Vector2::new(
+                        0.,
+                        (RESET_ROW + 2) as f32 * CONTROLS_MENU_ROW_HEIGHT,
+                    )))
+                    .ui(ui);
+            },
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::bindings::BindingsFile;
+    use crate::gamepad::GamepadButton;
+    use crate::gamepad::GamepadProfile;
+    use macroquad::prelude::KeyCode;
+
+    fn profiles() -> GamepadProfiles {
+        let profiles: Vec<GamepadProfile> = serde_json::from_str(
+            r#"[
+                { "name": "standard", "dead_zone": 0.3, "repeat_delay": 0.5, "repeat_interval": 0.25, "buttons": { "MoveDown": ["DPadDown"], "Use": ["South"] } },
+                { "name": "nintendo", "dead_zone": 0.3, "repeat_delay": 0.5, "repeat_interval": 0.25, "buttons": { "MoveDown": ["DPadDown"], "Use": ["East"] } }
+            ]"#,
+        )
+        .unwrap();
+        GamepadProfiles { profiles }
+    }
+
+    fn bindings(profiles: &GamepadProfiles) -> KeyBindings {
+        let file: BindingsFile = serde_json::from_str(
+            r#"{ "keys": { "MoveUp": ["W"], "MoveDown": ["S"], "Use": ["Space"], "Cancel": ["Escape"], "Menu": ["Tab"] } }"#,
+        )
+        .unwrap();
+        KeyBindings::from_file(&file, profiles.profiles[0].clone()).unwrap()
+    }
+
+    #[test]
+    fn test_rebind_through_menu() {
+        let profiles = profiles();
+        let mut bindings = bindings(&profiles);
+        let mut menu = ControlsMenu {
+            ..Default::default()
+        };
+        let defaults = bindings.clone();
+        let mut update = |menu: &mut ControlsMenu, input: Input| {
+            menu.update(&input, 0.1, &mut bindings, &defaults, &profiles)
+        };
+
+        // wraps around from the top to the reset row
+        let outcome = update(&mut menu, Input::default().press(KeyCode::W));
+        assert_eq!(outcome, MenuOutcome::Open);
+        assert_eq!(menu.selected, RESET_ROW);
+        update(&mut menu, Input::default().press(KeyCode::S));
+        assert_eq!(menu.selected, 0);
+
+        // rebind MoveUp to I, then check the I key moves the cursor up
+        update(&mut menu, Input::default().press(KeyCode::Space));
+        assert!(menu.rebinding);
+        let outcome = update(&mut menu, Input::default().press(KeyCode::I));
+        assert_eq!(outcome, MenuOutcome::Changed);
+        assert!(!menu.rebinding);
+        update(&mut menu, Input::default().press(KeyCode::I));
+        assert_eq!(menu.selected, RESET_ROW);
+
+        // Cancel gives up on rebinding, then closes the menu
+        update(&mut menu, Input::default().press(KeyCode::S));
+        update(&mut menu, Input::default().press(KeyCode::Space));
+        let outcome = update(&mut menu, Input::default().press(KeyCode::Escape));
+        assert_eq!(outcome, MenuOutcome::Open);
+        assert!(!menu.rebinding);
+        let outcome = update(&mut menu, Input::default().press(KeyCode::Escape));
+        assert_eq!(outcome, MenuOutcome::Closed);
+        assert_eq!(bindings.keys(InputAction::MoveUp), &[KeyCode::I]);
+    }
+
+    #[test]
+    fn test_pick_gamepad_profile() {
+        let profiles = profiles();
+        let mut bindings = bindings(&profiles);
+        let defaults = bindings.clone();
+        let mut menu = ControlsMenu {
+            ..Default::default()
+        };
+
+        // holding down on the d-pad repeats, 0.5 seconds after the first press then every 0.25 seconds
+        menu.update(
+            &Input::default().press_button(GamepadButton::DPadDown),
+            0.125,
+            &mut bindings,
+            &defaults,
+            &profiles,
+        );
+        for _ in 0..7 {
+            menu.update(
+                &Input::default().hold_button(GamepadButton::DPadDown),
+                0.125,
+                &mut bindings,
+                &defaults,
+                &profiles,
+            );
+        }
+        assert_eq!(menu.selected, 3);
+
+        // South is Use in the standard profile, and picks the next profile, where it isn't any more
+        menu.selected = GAMEPAD_PROFILE_ROW;
+        let use_button = Input::default().press_button(GamepadButton::South);
+        let outcome = menu.update(&use_button, 0.1, &mut bindings, &defaults, &profiles);
+        assert_eq!(outcome, MenuOutcome::Changed);
+        assert_eq!(bindings.gamepad.name, "nintendo");
+        let outcome = menu.update(&use_button, 0.1, &mut bindings, &defaults, &profiles);
+        assert_eq!(outcome, MenuOutcome::Open);
+        assert_eq!(bindings.gamepad.name, "nintendo");
+    }
+
+    #[test]
+    fn test_reset_to_defaults() {
+        let profiles = profiles();
+        let defaults = bindings(&profiles);
+        let mut bindings = defaults.clone();
+        bindings.rebind(InputAction::Use, KeyCode::E);
+        bindings.gamepad = profiles.profiles[1].clone();
+        let mut menu = ControlsMenu {
+            selected: RESET_ROW,
+            ..Default::default()
+        };
+        let outcome = menu.update(
+            &Input::default().press(KeyCode::E),
+            0.1,
+            &mut bindings,
+            &defaults,
+            &profiles,
+        );
+        assert_eq!(outcome, MenuOutcome::Changed);
+        assert_eq!(bindings, defaults);
+    }
+}
@@ -0,0 +1,103 @@
+use crate::actions::Action;
+use crate::platform::load_texture;
+use macroquad::prelude::*;
+use serde::Deserialize;
+use std::collections::HashMap;
+
+// A dialog tree file is a list of nodes, which link to each other by id.
+// Node ids are shared across all loaded files, so a conversation can start in one file and continue in another.
+#[derive(Deserialize)]
+struct DialogTreeFile {
+    nodes: Vec<DialogNode>,
+}
+
+// A single "screen" of a conversation. The text may span multiple pages of the dialog box.
+// speaker, text and choice text are all string ids, looked up in the string tables (see localization.rs)
+#[derive(Deserialize, Debug)]
+pub struct DialogNode {
+    pub id: String,
+    #[serde(default)]
+    pub speaker: Option<String>,
+    pub text: String,
+    #[serde(default)]
+    pub portrait: Option<String>, // texture path, loaded along with the tree
+    #[serde(default)]
+    pub choices: Vec<DialogChoice>,
+    // when there are no choices, these are used once the player finishes reading the node
+    #[serde(default)]
+    pub next: Option<String>,
+    #[serde(default)]
+    pub actions: Vec<Action>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct DialogChoice {
+    pub text: String,
+    #[serde(default)]
+    pub next: Option<String>,
+    #[serde(default)]
+    pub actions: Vec<Action>,
+}
+
+// global resource containing every dialog node we know about, keyed by node id
+#[derive(Default)]
+pub struct DialogTrees {
+    pub nodes: HashMap<String, DialogNode>,
+    pub portraits: HashMap<String, Texture2D>,
+}
+
+impl DialogTrees {
+    pub async fn load(paths: &[&str]) -> DialogTrees {
+        let mut dialog_trees = DialogTrees {
+            ..Default::default()
+        };
+        for path in paths {
+            let tree_json_bytes = load_file(path)
+                .await
+                .unwrap_or_else(|_| panic!("failed to load {}", path));
+            let tree_file: DialogTreeFile = serde_json::from_slice(&tree_json_bytes)
+                .unwrap_or_else(|err| panic!("failed to parse dialog tree {}: {}", path, err));
+            for node in tree_file.nodes {
+                if let Some(portrait) = &node.portrait {
+                    if !dialog_trees.portraits.contains_key(portrait) {
+                        let texture = load_texture(portrait).await;
+                        dialog_trees.portraits.insert(portrait.to_owned(), texture);
+                    }
+                }
+                dialog_trees.nodes.insert(node.id.to_owned(), node);
+            }
+        }
+        dialog_trees.check_links();
+        dialog_trees
+    }
+
+    // every string id used by any dialog node, so they can be checked against the string tables
+    pub fn string_keys(&self) -> Vec<&String> {
+        let mut keys = vec![];
+        for node in self.nodes.values() {
+            keys.push(&node.text);
+            keys.extend(node.speaker.iter());
+            keys.extend(node.choices.iter().map(|choice| &choice.text));
+        }
+        keys
+    }
+
+    // warn about any links to nodes that don't exist, since those would silently end the conversation
+    fn check_links(&self) {
+        for node in self.nodes.values() {
+            let links = node
+                .choices
+                .iter()
+                .filter_map(|choice| choice.next.as_ref())
+                .chain(node.next.iter());
+            for link in links {
+                if !self.nodes.contains_key(link) {
+                    println!(
+                        "WARNING: dialog node {} links to unknown node {}",
+                        node.id, link
+                    );
+                }
+            }
+        }
+    }
+}
@@ -2,11 +2,14 @@ use crate::Direction;
 use crate::GridPosition;
 use specs::Entity;
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq)]
 pub enum Event {
     // Fired when an entity tries to move in a particular direction
     EntityTriesMove(Entity, Direction),
 
+    // Fired when an entity turns to face a particular direction without moving
+    EntityTurns(Entity, Direction),
+
     // Fired when player presses Use button
     PlayerTriesUse(),
 
@@ -15,6 +18,9 @@ pub enum Event {
 
     // Fired when player successfully enters a tile position
     PlayerEntered(GridPosition),
+
+    // Fired when player confirms a choice on the last page of a dialog box (dialog id, choice index)
+    DialogChoiceSelected(u32, usize),
 }
 
 // global event queue
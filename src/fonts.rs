@@ -0,0 +1,74 @@
+use crate::constants::FONT_CHARACTER_RANGES;
+use crate::constants::FONT_SIZE;
+use crate::FontAtlas;
+use std::collections::BTreeSet;
+use ttf_parser::Face;
+
+// The font used for UI text.
+// megaui builds its atlas from a single font, so there is no per-glyph fallback to another font:
+// characters this font has no glyph for are left out of the atlas, drawn blank, and reported at startup.
+const FONT_NAME: &str = "Roboto-Bold";
+const FONT_BYTES: &[u8] = include_bytes!("../assets/fonts/Roboto-Bold.ttf");
+
+pub struct UiFont {
+    characters: Vec<char>,
+    pub missing_glyphs: Vec<char>,
+}
+
+impl UiFont {
+    // builds a font atlas containing every character the font has a glyph for.
+    // this can be called multiple times, since megaui takes ownership of the atlas it renders with.
+    pub fn build_atlas(&self) -> FontAtlas {
+        FontAtlas::new(FONT_BYTES, FONT_SIZE, self.characters.to_vec())
+            .unwrap_or_else(|err| panic!("failed to build font atlas for {}: {:?}", FONT_NAME, err))
+    }
+}
+
+// the configured character ranges, to be extended with any characters found in game text
+pub fn base_characters() -> BTreeSet<char> {
+    FONT_CHARACTER_RANGES
+        .iter()
+        .flat_map(|&(first, last)| first..=last)
+        .collect()
+}
+
+pub fn load_font(required: &BTreeSet<char>) -> UiFont {
+    let face = Face::from_slice(FONT_BYTES, 0)
+        .unwrap_or_else(|err| panic!("failed to parse font {}: {:?}", FONT_NAME, err));
+    let (characters, missing_glyphs): (Vec<char>, Vec<char>) = required
+        .iter()
+        .copied()
+        .partition(|&c| c.is_whitespace() || face.glyph_index(c).is_some());
+    if !missing_glyphs.is_empty() {
+        println!(
+            "WARNING: font {} is missing glyphs for {:?}, they will be drawn blank",
+            FONT_NAME, missing_glyphs
+        );
+    }
+    UiFont {
+        characters,
+        missing_glyphs,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_load_font_covers_latin() {
+        let mut required = base_characters();
+        required.extend("Crème brûlée, señor!".chars());
+        let font = load_font(&required);
+        assert!(font.missing_glyphs.is_empty());
+    }
+
+    #[test]
+    fn test_load_font_reports_missing_glyphs() {
+        let required = "ok 漢".chars().collect();
+        let font = load_font(&required);
+        assert_eq!(font.missing_glyphs, vec!['漢']);
+        assert!(font.characters.contains(&'o'));
+    }
+}
@@ -0,0 +1,170 @@
+use crate::actions::ActionQueue;
+use crate::actions::FiredTriggers;
+use crate::bindings::DefaultKeyBindings;
+use crate::bindings::KeyBindings;
+use crate::camera::Camera;
+use crate::clock::GameClock;
+use crate::components::AwaitingInputState;
+use crate::components::BlocksMovement;
+use crate::components::Elevation;
+use crate::components::FacingDirection;
+use crate::components::GridPosition;
+use crate::components::LevelEntity;
+use crate::components::PersistentId;
+use crate::components::Player;
+use crate::components::PlayerEntity;
+use crate::components::SpriteDrawable;
+use crate::constants::DEFAULT_PLAYER_NAME;
+use crate::constants::PREFAB_FILE;
+use crate::constants::VIEWPORT_HEIGHT;
+use crate::constants::VIEWPORT_WIDTH;
+use crate::dialogs::DialogTrees;
+use crate::events::EventQueue;
+use crate::gamepad::GamepadProfiles;
+use crate::level::change_level;
+use crate::level::PendingLevelChange;
+use crate::localization::Localization;
+use crate::map::GameMap;
+use crate::pathfinding::Pathfinder;
+use crate::platform::Input;
+use crate::platform::Rng;
+use crate::platform::Time;
+use crate::prefabs::ComponentBundle;
+use crate::prefabs::Prefabs;
+use crate::recording::ReplayState;
+use crate::save::handle_save_request;
+use crate::save::SaveSlots;
+use crate::ui::UiState;
+use crate::FontAtlas;
+use specs::Builder;
+use specs::World;
+use specs::WorldExt;
+
+// Setting up the World, and what happens in between frames. Shared by the game itself (main.rs) and headless simulations (simulation.rs).
+
+// creates the World with the player and all the global resources, ready for the systems to be set up on it.
+// the level's own entities are spawned by spawn_level_entities once the systems have registered their components.
+pub async fn create_world(
+    level_id: &str,
+    font_atlas: FontAtlas,
+    localization: Localization,
+    dialog_trees: DialogTrees,
+    rng: Rng,
+    key_bindings: KeyBindings,
+    gamepad_profiles: GamepadProfiles,
+) -> World {
+    let mut world = World::new();
+    // We only need to explicitly register the components used by Player entity, the rest get setup by dispatcher.setup(...)
+    world.register::<Player>();
+    world.register::<BlocksMovement>();
+    world.register::<GridPosition>();
+    world.register::<SpriteDrawable>();
+    world.register::<FacingDirection>();
+    world.register::<AwaitingInputState>();
+    world.register::<Elevation>();
+    world.register::<LevelEntity>();
+    world.register::<PersistentId>();
+
+    // Create player entity, from the "player" prefab plus the components only the player has
+    let prefabs = Prefabs::load(PREFAB_FILE).await;
+    let player_components = prefabs
+        .bundle(Some("player"), ComponentBundle::new())
+        .unwrap_or_else(|err| panic!("failed to create player: {}", err));
+    let player_builder = world
+        .create_entity()
+        .with(Player {})
+        // .with(GridPosition { x: 9., y: 3. })
+        .with(GridPosition { x: 2., y: 4. })
+        .with(Elevation { index: 0 })
+        .with(PersistentId {
+            id: "player".to_owned(),
+        })
+        .with(AwaitingInputState {});
+    let player_entity = prefabs
+        .add_components("player", player_builder, &player_components)
+        .unwrap_or_else(|err| panic!("failed to create player: {}", err))
+        .build();
+    world.insert(prefabs);
+
+    // Insert global resources
+    let map = GameMap::load(level_id).await;
+    world.insert(Pathfinder::new(&map));
+    world.insert(map);
+    world.insert(PendingLevelChange {
+        ..Default::default()
+    });
+    world.insert(EventQueue {
+        ..Default::default()
+    });
+    world.insert(ActionQueue {
+        ..Default::default()
+    });
+    world.insert(FiredTriggers {
+        ..Default::default()
+    });
+    world.insert(SaveSlots {
+        ..Default::default()
+    });
+    world.insert(ReplayState {
+        ..Default::default()
+    });
+    world.insert(Time {
+        ..Default::default()
+    });
+    world.insert(GameClock {
+        ..Default::default()
+    });
+    world.insert(Input {
+        ..Default::default()
+    });
+    world.insert(rng);
+    world.insert(DefaultKeyBindings {
+        bindings: KeyBindings::load_defaults(&gamepad_profiles).await,
+    });
+    world.insert(key_bindings);
+    world.insert(gamepad_profiles);
+    world.insert(dialog_trees);
+    world.insert(Camera {
+        view_width: VIEWPORT_WIDTH,
+        view_height: VIEWPORT_HEIGHT,
+        ..Default::default()
+    });
+    world.insert(PlayerEntity {
+        entity: player_entity,
+    });
+    world.insert(UiState {
+        font_atlas,
+        localization,
+        player_name: DEFAULT_PLAYER_NAME.to_owned(),
+        dialog_box: None,
+        conversation_node: None,
+        controls_menu: None,
+    });
+    world
+}
+
+// everything the main loop does in between frames, after the systems have run
+pub async fn finish_frame(world: &mut World) {
+    // travel to another level, if an action asked for it this frame
+    let level_change = world
+        .write_resource::<PendingLevelChange>()
+        .level_change
+        .take();
+    if let Some(level_change) = level_change {
+        change_level(world, level_change).await;
+    }
+
+    // save or load the game, if the player asked for it this frame
+    handle_save_request(world).await;
+
+    // handle events
+    let mut event_queue = world.write_resource::<EventQueue>();
+    if !event_queue.events.is_empty() {
+        println!("current events: {:?}", event_queue.events);
+    }
+    if !event_queue.new_events.is_empty() {
+        println!("new events: {:?}", event_queue.new_events);
+    }
+    event_queue.events = (*event_queue.new_events).to_vec();
+    event_queue.new_events.clear();
+}
@@ -0,0 +1,256 @@
+use crate::bindings::InputAction;
+use crate::constants::GAMEPAD_PROFILES_FILE;
+use macroquad::prelude::load_file;
+use serde::Deserialize;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::collections::HashSet;
+
+// Gamepads are read with gilrs, since macroquad only knows about keyboards. Whatever the pad, its buttons are mapped onto
+// GamepadButton, and the left stick becomes four more "buttons" once it's pushed past the dead zone, so movement on the stick
+// works just like the d-pad. Which buttons do what is up to the player's gamepad profile (see assets/gamepad_profiles.json).
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum GamepadButton {
+    DPadUp,
+    DPadDown,
+    DPadLeft,
+    DPadRight,
+    StickUp,
+    StickDown,
+    StickLeft,
+    StickRight,
+    South, // A on an Xbox pad, B on a Nintendo one
+    East,
+    North,
+    West,
+    LeftShoulder,
+    RightShoulder,
+    Start,
+    Select,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct GamepadProfile {
+    pub name: String,
+    pub dead_zone: f32, // how far (0 to 1) the stick has to be pushed before it counts
+    pub repeat_delay: f32, // seconds a direction is held in a menu before the cursor starts repeating
+    pub repeat_interval: f32, // seconds between repeats after that
+    pub buttons: BTreeMap<InputAction, Vec<GamepadButton>>,
+}
+
+impl Default for GamepadProfile {
+    // no buttons at all, so only the keyboard works
+    fn default() -> GamepadProfile {
+        GamepadProfile {
+            name: "none".to_owned(),
+            dead_zone: 0.5,
+            repeat_delay: 0.4,
+            repeat_interval: 0.12,
+            buttons: BTreeMap::new(),
+        }
+    }
+}
+
+impl GamepadProfile {
+    pub fn buttons(&self, action: InputAction) -> &[GamepadButton] {
+        self.buttons
+            .get(&action)
+            .map_or(&[], |buttons| buttons.as_slice())
+    }
+}
+
+// global resource with every gamepad profile the player can pick, in the order the controls menu cycles through them
+#[derive(Debug, Clone, Default)]
+pub struct GamepadProfiles {
+    pub profiles: Vec<GamepadProfile>,
+}
+
+impl GamepadProfiles {
+    pub async fn load() -> GamepadProfiles {
+        let json_bytes = load_file(GAMEPAD_PROFILES_FILE)
+            .await
+            .unwrap_or_else(|_| panic!("failed to load {}", GAMEPAD_PROFILES_FILE));
+        let profiles: Vec<GamepadProfile> = serde_json::from_slice(&json_bytes)
+            .unwrap_or_else(|err| panic!("failed to parse {}: {}", GAMEPAD_PROFILES_FILE, err));
+        if profiles.is_empty() {
+            panic!("{} has no profiles", GAMEPAD_PROFILES_FILE);
+        }
+        GamepadProfiles { profiles }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&GamepadProfile> {
+        self.profiles.iter().find(|profile| profile.name == name)
+    }
+
+    // the profile after the named one, wrapping around to the first
+    pub fn next(&self, name: &str) -> &GamepadProfile {
+        let index = self
+            .profiles
+            .iter()
+            .position(|profile| profile.name == name)
+            .map_or(0, |index| (index + 1) % self.profiles.len());
+        &self.profiles[index]
+    }
+}
+
+// which way the stick is pushed, if it's past the dead zone. only the stronger axis counts, since movement is on a grid.
+// y is up, like gilrs.
+pub fn stick_direction(x: f32, y: f32, dead_zone: f32) -> Option<GamepadButton> {
+    if (x * x + y * y).sqrt() < dead_zone {
+        return None;
+    }
+    if x.abs() > y.abs() {
+        Some(if x > 0. {
+            GamepadButton::StickRight
+        } else {
+            GamepadButton::StickLeft
+        })
+    } else {
+        Some(if y > 0. {
+            GamepadButton::StickUp
+        } else {
+            GamepadButton::StickDown
+        })
+    }
+}
+
+// Reads every connected gamepad once a frame, as if they were all one pad, which is plenty for a single player game.
+pub struct Gamepads {
+    #[cfg(not(target_arch = "wasm32"))]
+    gilrs: Option<gilrs::Gilrs>,
+    buttons_down: HashSet<GamepadButton>, // as of the last frame, to tell which buttons went down since
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+const GILRS_BUTTONS: &[(gilrs::Button, GamepadButton)] = &[
+    (gilrs::Button::DPadUp, GamepadButton::DPadUp),
+    (gilrs::Button::DPadDown, GamepadButton::DPadDown),
+    (gilrs::Button::DPadLeft, GamepadButton::DPadLeft),
+    (gilrs::Button::DPadRight, GamepadButton::DPadRight),
+    (gilrs::Button::South, GamepadButton::South),
+    (gilrs::Button::East, GamepadButton::East),
+    (gilrs::Button::North, GamepadButton::North),
+    (gilrs::Button::West, GamepadButton::West),
+    (gilrs::Button::LeftTrigger, GamepadButton::LeftShoulder),
+    (gilrs::Button::RightTrigger, GamepadButton::RightShoulder),
+    (gilrs::Button::Start, GamepadButton::Start),
+    (gilrs::Button::Select, GamepadButton::Select),
+];
+
+impl Gamepads {
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn connect() -> Gamepads {
+        // no gamepad support is fine, there's always the keyboard
+        let gilrs = match gilrs::Gilrs::new() {
+            Ok(gilrs) => Some(gilrs),
+            Err(err) => {
+                println!("WARNING: gamepads aren't available: {}", err);
+                None
+            }
+        };
+        Gamepads {
+            gilrs,
+            buttons_down: HashSet::new(),
+        }
+    }
+
+    // TODO: the browser build could read the Gamepad API through miniquad
+    #[cfg(target_arch = "wasm32")]
+    pub fn connect() -> Gamepads {
+        Gamepads {
+            buttons_down: HashSet::new(),
+        }
+    }
+
+    // the buttons that are down, and those that went down this frame
+    pub fn read(&mut self, dead_zone: f32) -> (HashSet<GamepadButton>, HashSet<GamepadButton>) {
+        let buttons_down = self.buttons_down(dead_zone);
+        let buttons_pressed = buttons_down
+            .difference(&self.buttons_down)
+            .copied()
+            .collect();
+        self.buttons_down = buttons_down.clone();
+        (buttons_down, buttons_pressed)
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn buttons_down(&mut self, dead_zone: f32) -> HashSet<GamepadButton> {
+        let mut buttons_down = HashSet::new();
+        let gilrs = match self.gilrs.as_mut() {
+            Some(gilrs) => gilrs,
+            None => return buttons_down,
+        };
+        // gilrs keeps each pad's state up to date as its events are handled
+        while gilrs.next_event().is_some() {}
+        for (_, gamepad) in gilrs.gamepads() {
+            for (gilrs_button, button) in GILRS_BUTTONS.iter() {
+                if gamepad.is_pressed(*gilrs_button) {
+                    buttons_down.insert(*button);
+                }
+            }
+            let x = gamepad.value(gilrs::Axis::LeftStickX);
+            let y = gamepad.value(gilrs::Axis::LeftStickY);
+            if let Some(direction) = stick_direction(x, y, dead_zone) {
+                buttons_down.insert(direction);
+            }
+        }
+        buttons_down
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    fn buttons_down(&mut self, _dead_zone: f32) -> HashSet<GamepadButton> {
+        HashSet::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_stick_direction() {
+        assert_eq!(stick_direction(0.1, -0.2, 0.3), None);
+        assert_eq!(
+            stick_direction(0.25, 0.25, 0.3),
+            Some(GamepadButton::StickUp)
+        );
+        assert_eq!(
+            stick_direction(0.9, -0.4, 0.3),
+            Some(GamepadButton::StickRight)
+        );
+        assert_eq!(
+            stick_direction(-0.2, -0.7, 0.3),
+            Some(GamepadButton::StickDown)
+        );
+        assert_eq!(
+            stick_direction(-1., 0., 0.3),
+            Some(GamepadButton::StickLeft)
+        );
+    }
+
+    #[test]
+    fn test_profiles() {
+        let profiles: Vec<GamepadProfile> = serde_json::from_str(
+            r#"[
+                { "name": "standard", "dead_zone": 0.3, "repeat_delay": 0.4, "repeat_interval": 0.1, "buttons": { "Use": ["South"] } },
+                { "name": "nintendo", "dead_zone": 0.3, "repeat_delay": 0.4, "repeat_interval": 0.1, "buttons": { "Use": ["East"] } }
+            ]"#,
+        )
+        .unwrap();
+        let profiles = GamepadProfiles { profiles };
+        assert_eq!(
+            profiles.get("nintendo").unwrap().buttons(InputAction::Use),
+            &[GamepadButton::East]
+        );
+        assert!(profiles
+            .get("nintendo")
+            .unwrap()
+            .buttons(InputAction::Cancel)
+            .is_empty());
+        assert!(profiles.get("playstation").is_none());
+        assert_eq!(profiles.next("standard").name, "nintendo");
+        assert_eq!(profiles.next("nintendo").name, "standard");
+        assert_eq!(profiles.next("playstation").name, "standard");
+    }
+}
@@ -0,0 +1,361 @@
+use crate::actions::Action;
+use crate::components::Direction;
+use crate::components::Elevation;
+use crate::components::FacingDirection;
+use crate::components::GridPosition;
+use crate::components::LevelEntity;
+use crate::components::PersistentId;
+use crate::components::PlayerEntity;
+use crate::components::SpriteDrawable;
+use crate::components::TriggerActionOnDialogChoice;
+use crate::components::TriggerActionOnEnter;
+use crate::components::TriggerActionOnExit;
+use crate::components::TriggerActionOnUse;
+use crate::components::TriggerOnce;
+use crate::map::GameMap;
+use crate::map::MapObject;
+use crate::pathfinding::Pathfinder;
+use crate::prefabs::ComponentBundle;
+use crate::prefabs::Prefabs;
+use crate::ui::DialogBoxConf;
+use macroquad::prelude::*;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::json;
+use serde_json::Value;
+use specs::Builder;
+use specs::Entity;
+use specs::Join;
+use specs::World;
+use specs::WorldExt;
+
+// A level describes how to load and use a Tiled map, so that any map can be used without code changes.
+// Level files live in assets/levels/<level id>.json
+#[derive(Deserialize, Debug, Clone)]
+pub struct LevelConf {
+    pub map: String, // path to the Tiled JSON export
+    pub tilesets: Vec<TilesetConf>,
+    // layers drawn below sprites, in order
+    #[serde(default)]
+    pub background_layers: Vec<String>,
+    // the heights an entity can be at in this map, from the ground up (e.g. the floor, then the top of a platform)
+    #[serde(default)]
+    pub elevations: Vec<ElevationConf>,
+    pub tile_width: f32,
+    pub tile_height: f32,
+}
+
+// Sprites on an elevation are drawn on top of everything belonging to the elevations below it,
+// so a character upstairs isn't hidden by the foreground of the floor below.
+#[derive(Deserialize, Debug, Clone)]
+pub struct ElevationConf {
+    // layers drawn on top of this elevation's sprites, to simulate depth
+    #[serde(default)]
+    pub foreground_layers: Vec<String>,
+    // tiles present in this layer block movement on this elevation. without one, nothing is blocked.
+    #[serde(default)]
+    pub collision_layer: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct TilesetConf {
+    pub image: String,   // image name as referenced by the Tiled map
+    pub texture: String, // path to the texture to use for it
+}
+
+impl LevelConf {
+    pub async fn load(level_id: &str) -> LevelConf {
+        let path = format!("assets/levels/{}.json", level_id);
+        let level_json_bytes = load_file(&path)
+            .await
+            .unwrap_or_else(|_| panic!("failed to load {}", path));
+        serde_json::from_slice(&level_json_bytes)
+            .unwrap_or_else(|err| panic!("failed to parse level {}: {}", path, err))
+    }
+}
+
+// global resource set by the ActionSystem when the player should travel to another level.
+// the level is actually changed by the main loop in between frames, since loading it is async and needs the whole World.
+#[derive(Default)]
+pub struct PendingLevelChange {
+    pub level_change: Option<LevelChange>,
+}
+
+#[derive(Debug)]
+pub struct LevelChange {
+    pub level_id: String,
+    pub position: GridPosition, // where the player arrives in the new level
+    pub facing: Direction,
+}
+
+// unloads the current level's entities, loads the new level and its entities, and places the player in it
+pub async fn change_level(world: &mut World, level_change: LevelChange) {
+    println!("Changing level: {:?}", level_change);
+    let level_entities: Vec<Entity> = {
+        let entities = world.entities();
+        let level_markers = world.read_storage::<LevelEntity>();
+        (&entities, &level_markers)
+            .join()
+            .map(|(entity, _)| entity)
+            .collect()
+    };
+    world
+        .delete_entities(&level_entities)
+        .expect("failed to delete level entities");
+
+    let map = GameMap::load(&level_change.level_id).await;
+    world.insert(Pathfinder::new(&map));
+    world.insert(map);
+    spawn_level_entities(world);
+
+    let player_entity = world.read_resource::<PlayerEntity>().entity;
+    world
+        .write_storage::<GridPosition>()
+        .insert(player_entity, level_change.position)
+        .expect("failed to move player to new level");
+    world
+        .write_storage::<FacingDirection>()
+        .insert(
+            player_entity,
+            FacingDirection {
+                direction: level_change.facing,
+            },
+        )
+        .expect("failed to turn player in new level");
+    // every level is entered on the ground
+    world
+        .write_storage::<Elevation>()
+        .insert(player_entity, Elevation { index: 0 })
+        .expect("failed to reset player elevation in new level");
+    if let Some(drawable) = world
+        .write_storage::<SpriteDrawable>()
+        .get_mut(player_entity)
+    {
+        drawable.current_frame = level_change.facing.get_character_facing_frame();
+    }
+}
+
+// Creates all the non-player entities (triggers, NPCs, props, etc.) from the objects placed in the map's object layers.
+// Every one of them is marked with LevelEntity, so they get cleaned up when the player leaves the level,
+// and gets a PersistentId made from the level id and the Tiled object id, so save files can refer to it.
+//
+// Objects are configured with these custom properties in Tiled, all optional:
+// - prefab (string): start from this prefab's components (see prefabs.rs), e.g. a villager
+// - components (JSON): components to add or override, in the same format as a prefab
+// - sprite_row (int): shorthand for overriding the prefab sprite's row in the texture
+// - facing (string): direction a sprite starts out facing (Up, Down, Left, Right), defaults to Down
+// - blocks (bool): the object blocks movement
+// - elevation (int): which of the level's elevations the object is on, defaults to 0 (the ground).
+//   the player only triggers objects on the same elevation.
+// - change_elevation (int): move the player to this elevation, for stairs and ladders
+// - stroll_pause (float): the object strolls around, pausing up to this many seconds between steps
+// - stroll_radius (float): a strolling object stays within this many tiles of where it's placed
+// - stroll_area (string): a strolling object stays inside the area object with this name
+// - teleport_x / teleport_y (int): teleport the player to this position, in another level if teleport_level is set,
+//   facing teleport_facing (defaults to Down)
+// - dialog (string id): show a dialog box, with an optional title (string id) and choices (comma-separated string ids).
+//   dialog_id (int) and choice_actions (JSON list of actions, one per choice) make the choices do something.
+// - conversation (string): start the conversation with this dialog tree node id
+// - message (string): print a message to the console
+// - action (JSON): any other action, written the way it would be in a dialog tree file
+// - once (bool): the action only triggers the first time, even if the player leaves the level and comes back
+// Objects of type "area" only mark out part of the map (e.g. for stroll_area), and don't become entities.
+// The object's type decides when its action triggers: "door", "stairs" and "ladder" on entering the tile, "marker" on leaving it,
+// and anything else when the player uses it. The trigger property (enter, exit or use) overrides this.
+pub fn spawn_level_entities(world: &mut World) {
+    let (level_id, objects) = {
+        let map = world.read_resource::<GameMap>();
+        (map.level_id.to_owned(), map.objects.clone())
+    };
+    let prefabs = world.read_resource::<Prefabs>().clone();
+    for object in objects.iter() {
+        if object.kind == "area" {
+            continue;
+        }
+        let source = format!("map object {}", object.name);
+        let bundle = prefabs
+            .bundle(
+                object.property_str("prefab"),
+                object_components(object, &objects),
+            )
+            .unwrap_or_else(|err| panic!("failed to spawn {}: {}", source, err));
+        let mut builder = world
+            .create_entity()
+            .with(LevelEntity)
+            .with(PersistentId {
+                id: format!("{}/{}", level_id, object.id),
+            })
+            .with(object.position);
+        if object.property_bool("once") {
+            builder = builder.with(TriggerOnce);
+        }
+        builder = prefabs
+            .add_components(&source, builder, &bundle)
+            .unwrap_or_else(|err| panic!("failed to spawn {}: {}", source, err));
+        if let Some(action) = object_action(object) {
+            let trigger =
+                object
+                    .property_str("trigger")
+                    .unwrap_or_else(|| match object.kind.as_str() {
+                        "door" | "stairs" | "ladder" => "enter",
+                        "marker" => "exit",
+                        _ => "use",
+                    });
+            builder = match trigger {
+                "enter" => builder.with(TriggerActionOnEnter { action }),
+                "exit" => builder.with(TriggerActionOnExit { action }),
+                "use" => builder.with(TriggerActionOnUse { action }),
+                _ => panic!("unknown trigger {} on map object {}", trigger, object.name),
+            };
+        }
+        if let Some(choice_actions) = object.properties.get("choice_actions") {
+            let actions = parse_json_property(object, choice_actions);
+            let dialog_id = object.property_f32("dialog_id").unwrap_or_else(|| {
+                panic!(
+                    "map object {} has choice_actions but no dialog_id",
+                    object.name
+                )
+            });
+            builder = builder.with(TriggerActionOnDialogChoice {
+                dialog_id: dialog_id as u32,
+                actions,
+            });
+        }
+        builder.build();
+    }
+}
+
+// turns the map object's shorthand properties into prefab components, which override anything from its prefab
+fn object_components(object: &MapObject, objects: &[MapObject]) -> ComponentBundle {
+    let mut components = ComponentBundle::new();
+    if let Some(row) = object.properties.get("sprite_row") {
+        components.insert("sprite".to_owned(), json!({ "row": row }));
+    }
+    if let Some(facing) = object.properties.get("facing") {
+        components.insert("facing".to_owned(), facing.clone());
+    }
+    if object.properties.contains_key("blocks") {
+        components.insert(
+            "blocks_movement".to_owned(),
+            Value::Bool(object.property_bool("blocks")),
+        );
+    }
+    let mut strolling = ComponentBundle::new();
+    if let Some(max_pause_seconds) = object.properties.get("stroll_pause") {
+        strolling.insert("max_pause_seconds".to_owned(), max_pause_seconds.clone());
+    }
+    if let Some(radius) = object.property_f32("stroll_radius") {
+        strolling.insert(
+            "area".to_owned(),
+            json!({ "x": object.position.x, "y": object.position.y, "radius": radius }),
+        );
+    }
+    if let Some(area_name) = object.property_str("stroll_area") {
+        let area = objects
+            .iter()
+            .find(|area| area.kind == "area" && area.name == area_name)
+            .unwrap_or_else(|| {
+                panic!(
+                    "no area {} for map object {} to stroll in",
+                    area_name, object.name
+                )
+            });
+        strolling.insert(
+            "area".to_owned(),
+            json!({
+                "x": area.position.x,
+                "y": area.position.y,
+                "width": area.width,
+                "height": area.height
+            }),
+        );
+    }
+    if !strolling.is_empty() {
+        components.insert("strolling".to_owned(), Value::Object(strolling));
+    }
+    if let Some(elevation) = object.properties.get("elevation") {
+        components.insert("elevation".to_owned(), elevation.clone());
+    }
+    if let Some(extra_components) = object.properties.get("components") {
+        let extra_components: ComponentBundle = parse_json_property(object, extra_components);
+        components.extend(extra_components);
+    }
+    components
+}
+
+// works out which action (if any) the map object's properties describe
+fn object_action(object: &MapObject) -> Option<Action> {
+    if let (Some(x), Some(y)) = (
+        object.property_f32("teleport_x"),
+        object.property_f32("teleport_y"),
+    ) {
+        let position = GridPosition { x, y };
+        return Some(match object.property_str("teleport_level") {
+            Some(level_id) => Action::ChangeLevel {
+                level_id: level_id.to_owned(),
+                position,
+                facing: object
+                    .property_str("teleport_facing")
+                    .map(|facing| parse_direction(object, facing))
+                    .unwrap_or(Direction::Down),
+            },
+            None => Action::Teleport(position),
+        });
+    }
+    if let Some(index) = object.property_f32("change_elevation") {
+        return Some(Action::ChangeElevation(index as usize));
+    }
+    if let Some(message) = object.property_str("dialog") {
+        let title = object.property_str("title");
+        let choices = object.property_str("choices");
+        let dialog_id = object.property_f32("dialog_id");
+        if title.is_none() && choices.is_none() && dialog_id.is_none() {
+            return Some(Action::ShowSimpleDialog(message.to_owned()));
+        }
+        return Some(Action::ShowDialog(DialogBoxConf {
+            id: dialog_id.unwrap_or(0.) as u32,
+            message: message.to_owned(),
+            title: title.map(|title| title.to_owned()),
+            choices: choices
+                .map(|choices| {
+                    choices
+                        .split(',')
+                        .map(|choice| choice.trim().to_owned())
+                        .collect()
+                })
+                .unwrap_or_default(),
+            ..Default::default()
+        }));
+    }
+    if let Some(node_id) = object.property_str("conversation") {
+        return Some(Action::StartConversation(node_id.to_owned()));
+    }
+    if let Some(message) = object.property_str("message") {
+        return Some(Action::PrintMessage(message.to_owned()));
+    }
+    object
+        .properties
+        .get("action")
+        .map(|action| parse_json_property(object, action))
+}
+
+fn parse_direction(object: &MapObject, facing: &str) -> Direction {
+    serde_json::from_value(Value::String(facing.to_owned()))
+        .unwrap_or_else(|_| panic!("invalid direction {} on map object {}", facing, object.name))
+}
+
+// JSON properties are written as strings in Tiled, so they need parsing a second time
+fn parse_json_property<T: DeserializeOwned>(object: &MapObject, value: &Value) -> T {
+    let json = value.as_str().unwrap_or_else(|| {
+        panic!(
+            "expected a JSON string property on map object {}",
+            object.name
+        )
+    });
+    serde_json::from_str(json).unwrap_or_else(|err| {
+        panic!(
+            "invalid JSON property on map object {}: {}",
+            object.name, err
+        )
+    })
+}
@@ -0,0 +1,113 @@
+use macroquad::prelude::*;
+use std::collections::BTreeSet;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::sync::Mutex;
+
+// All player-facing text is looked up by string id in a per-language string table (assets/strings/<language>.json).
+// Lookups fall back to the default language, and then to the id itself so that missing text is obvious in game.
+pub struct Localization {
+    pub language: String,
+    default_language: String,
+    tables: HashMap<String, HashMap<String, String>>,
+    // keys we've already warned about, since text is looked up every frame
+    warned_keys: Mutex<HashSet<String>>,
+}
+
+impl Localization {
+    // the first language is the default, used as a fallback for any keys missing from the others
+    pub async fn load(languages: &[&str]) -> Localization {
+        let mut tables = HashMap::new();
+        for language in languages {
+            let path = format!("assets/strings/{}.json", language);
+            let table_json_bytes = load_file(&path)
+                .await
+                .unwrap_or_else(|_| panic!("failed to load {}", path));
+            let table: HashMap<String, String> = serde_json::from_slice(&table_json_bytes)
+                .unwrap_or_else(|err| panic!("failed to parse string table {}: {}", path, err));
+            tables.insert(language.to_string(), table);
+        }
+        let localization = Localization {
+            language: languages[0].to_owned(),
+            default_language: languages[0].to_owned(),
+            tables,
+            warned_keys: Mutex::new(HashSet::new()),
+        };
+        localization.report_missing_keys();
+        localization
+    }
+
+    pub fn get(&self, key: &str) -> String {
+        for language in [&self.language, &self.default_language].iter() {
+            if let Some(text) = self.tables.get(*language).and_then(|table| table.get(key)) {
+                return text.to_owned();
+            }
+        }
+        if self.warned_keys.lock().unwrap().insert(key.to_owned()) {
+            println!("WARNING: missing string for key {}", key);
+        }
+        key.to_owned()
+    }
+
+    pub fn set_language(&mut self, language: &str) {
+        if self.tables.contains_key(language) {
+            self.language = language.to_owned();
+        } else {
+            println!("WARNING: tried to switch to unknown language {}", language);
+        }
+    }
+
+    // switches to the next loaded language (alphabetically), wrapping around
+    pub fn cycle_language(&mut self) {
+        let mut languages: Vec<&String> = self.tables.keys().collect();
+        languages.sort();
+        let current = languages
+            .iter()
+            .position(|language| **language == self.language)
+            .unwrap_or(0);
+        let next = languages[(current + 1) % languages.len()].to_owned();
+        self.set_language(&next);
+    }
+
+    // warns about any keys that are used (e.g. by dialog trees) but missing from the default language
+    pub fn check_keys(&self, keys: &[&String]) {
+        let default_table = &self.tables[&self.default_language];
+        for key in keys {
+            if !default_table.contains_key(*key) {
+                println!(
+                    "WARNING: string key {} is missing from default language {}",
+                    key, self.default_language
+                );
+            }
+        }
+    }
+
+    // every character used by any string in any language, so the font atlas can be built to include them
+    pub fn characters(&self) -> BTreeSet<char> {
+        self.tables
+            .values()
+            .flat_map(|table| table.values())
+            .flat_map(|text| text.chars())
+            .collect()
+    }
+
+    // reports which keys from the default language haven't been translated yet
+    fn report_missing_keys(&self) {
+        let default_table = &self.tables[&self.default_language];
+        for (language, table) in self.tables.iter() {
+            let mut missing: Vec<&String> = default_table
+                .keys()
+                .filter(|key| !table.contains_key(*key))
+                .collect();
+            if !missing.is_empty() {
+                missing.sort();
+                println!(
+                    "WARNING: language {} is missing {} strings: {:?}",
+                    language,
+                    missing.len(),
+                    missing
+                );
+            }
+        }
+    }
+}
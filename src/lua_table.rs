@@ -0,0 +1,288 @@
+use serde_json::Map;
+use serde_json::Value;
+
+// The RPG book ships its maps as Tiled's Lua export, so this converts one into the same shape as Tiled's JSON export.
+// It only understands the bit of Lua that Tiled writes: `return { ... }` with nested tables, strings, numbers and booleans.
+// Tables with only positional entries become arrays, anything with keys becomes an object.
+pub fn lua_table_to_json(source: &str) -> Result<Value, String> {
+    let mut parser = LuaParser {
+        chars: source.chars().collect(),
+        pos: 0,
+    };
+    parser.skip_whitespace();
+    if parser.peek_identifier() == "return" {
+        parser.pos += "return".len();
+    }
+    let value = parser.parse_value()?;
+    parser.skip_whitespace();
+    if parser.pos < parser.chars.len() {
+        return Err(parser.error("expected end of file"));
+    }
+    Ok(value)
+}
+
+struct LuaParser {
+    chars: Vec<char>,
+    pos: usize,
+}
+
+impl LuaParser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn error(&self, message: &str) -> String {
+        let line = self.chars[..self.pos.min(self.chars.len())]
+            .iter()
+            .filter(|c| **c == '\n')
+            .count()
+            + 1;
+        format!("{} on line {}", message, line)
+    }
+
+    // skips whitespace and -- comments
+    fn skip_whitespace(&mut self) {
+        loop {
+            while self.peek().map_or(false, char::is_whitespace) {
+                self.pos += 1;
+            }
+            if self.peek() == Some('-') && self.chars.get(self.pos + 1) == Some(&'-') {
+                while self.peek().map_or(false, |c| c != '\n') {
+                    self.pos += 1;
+                }
+            } else {
+                return;
+            }
+        }
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), String> {
+        self.skip_whitespace();
+        if self.peek() != Some(expected) {
+            return Err(self.error(&format!("expected '{}'", expected)));
+        }
+        self.pos += 1;
+        Ok(())
+    }
+
+    // returns the identifier starting at the current position, without consuming it
+    fn peek_identifier(&self) -> String {
+        self.chars[self.pos..]
+            .iter()
+            .take_while(|c| c.is_ascii_alphanumeric() || **c == '_')
+            .collect()
+    }
+
+    fn parse_value(&mut self) -> Result<Value, String> {
+        self.skip_whitespace();
+        match self.peek() {
+            Some('{') => self.parse_table(),
+            Some('"') | Some('\'') => self.parse_string().map(Value::String),
+            Some(c) if c == '-' || c == '.' || c.is_ascii_digit() => self.parse_number(),
+            Some(c) if c.is_ascii_alphabetic() || c == '_' => {
+                let identifier = self.peek_identifier();
+                let value = match identifier.as_str() {
+                    "true" => Value::Bool(true),
+                    "false" => Value::Bool(false),
+                    "nil" => Value::Null,
+                    _ => return Err(self.error(&format!("unexpected identifier {}", identifier))),
+                };
+                self.pos += identifier.len();
+                Ok(value)
+            }
+            Some(c) => Err(self.error(&format!("unexpected character '{}'", c))),
+            None => Err(self.error("unexpected end of file")),
+        }
+    }
+
+    fn parse_table(&mut self) -> Result<Value, String> {
+        self.expect('{')?;
+        let mut array = vec![];
+        let mut object = Map::new();
+        loop {
+            self.skip_whitespace();
+            match self.peek() {
+                Some('}') => {
+                    self.pos += 1;
+                    break;
+                }
+                Some('[') => {
+                    // ["key"] = value
+                    self.pos += 1;
+                    let key = match self.parse_value()? {
+                        Value::String(key) => key,
+                        key => key.to_string(),
+                    };
+                    self.expect(']')?;
+                    self.expect('=')?;
+                    object.insert(key, self.parse_value()?);
+                }
+                _ => {
+                    // key = value, or just a value
+                    let start = self.pos;
+                    let identifier = self.peek_identifier();
+                    self.pos += identifier.len();
+                    self.skip_whitespace();
+                    if !identifier.is_empty()
+                        && self.peek() == Some('=')
+                        && self.chars.get(self.pos + 1) != Some(&'=')
+                    {
+                        self.pos += 1;
+                        object.insert(identifier, self.parse_value()?);
+                    } else {
+                        self.pos = start;
+                        array.push(self.parse_value()?);
+                    }
+                }
+            }
+            self.skip_whitespace();
+            match self.peek() {
+                Some(',') | Some(';') => self.pos += 1,
+                Some('}') => (),
+                _ => return Err(self.error("expected ',' or '}'")),
+            }
+        }
+        if object.is_empty() {
+            return Ok(Value::Array(array));
+        }
+        // mixed tables are rare, but positional entries are keyed from 1 in Lua
+        for (index, value) in array.into_iter().enumerate() {
+            object.insert((index + 1).to_string(), value);
+        }
+        Ok(Value::Object(object))
+    }
+
+    fn parse_string(&mut self) -> Result<String, String> {
+        let quote = self.peek().expect("parse_string called without a quote");
+        self.pos += 1;
+        let mut string = String::new();
+        loop {
+            let c = self
+                .peek()
+                .ok_or_else(|| self.error("unterminated string"))?;
+            self.pos += 1;
+            if c == quote {
+                return Ok(string);
+            }
+            if c != '\\' {
+                string.push(c);
+                continue;
+            }
+            let escaped = self
+                .peek()
+                .ok_or_else(|| self.error("unterminated string"))?;
+            self.pos += 1;
+            match escaped {
+                'n' => string.push('\n'),
+                't' => string.push('\t'),
+                'r' => string.push('\r'),
+                c if c.is_ascii_digit() => {
+                    // \ddd is a decimal character code of up to 3 digits
+                    let mut code = c.to_digit(10).unwrap();
+                    for _ in 0..2 {
+                        match self.peek().and_then(|c| c.to_digit(10)) {
+                            Some(digit) => {
+                                code = code * 10 + digit;
+                                self.pos += 1;
+                            }
+                            None => break,
+                        }
+                    }
+                    string.push(std::char::from_u32(code).unwrap_or('?'));
+                }
+                c => string.push(c), // \\, \", \' and anything else we don't know about
+            }
+        }
+    }
+
+    fn parse_number(&mut self) -> Result<Value, String> {
+        let start = self.pos;
+        while self
+            .peek()
+            .map_or(false, |c| c.is_ascii_digit() || "+-.eE".contains(c))
+        {
+            self.pos += 1;
+        }
+        let number: String = self.chars[start..self.pos].iter().collect();
+        if let Ok(integer) = number.parse::<i64>() {
+            return Ok(Value::from(integer));
+        }
+        number
+            .parse::<f64>()
+            .map(Value::from)
+            .map_err(|_| self.error(&format!("invalid number {}", number)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_tiled_export() {
+        let source = r#"return {
+  version = "1.4",
+  orientation = "orthogonal",
+  width = 2,
+  height = 2,
+  infinite = false,
+  properties = {},
+  layers = {
+    {
+      type = "tilelayer",
+      name = "Tile Layer 1",
+      opacity = 0.5,
+      properties = {
+        ["blocks"] = true
+      },
+      encoding = "lua",
+      data = {
+        1, 2,
+        0, -1
+      }
+    }
+  }
+}
+"#;
+        assert_eq!(
+            lua_table_to_json(source).unwrap(),
+            json!({
+                "version": "1.4",
+                "orientation": "orthogonal",
+                "width": 2,
+                "height": 2,
+                "infinite": false,
+                "properties": [],
+                "layers": [{
+                    "type": "tilelayer",
+                    "name": "Tile Layer 1",
+                    "opacity": 0.5,
+                    "properties": { "blocks": true },
+                    "encoding": "lua",
+                    "data": [1, 2, 0, -1]
+                }]
+            })
+        );
+    }
+
+    #[test]
+    fn test_strings_and_comments() {
+        let source = "-- exported map\n{ 'single', \"say \\\"hi\\\"\\n\", \"\\65\" } -- trailing";
+        assert_eq!(
+            lua_table_to_json(source).unwrap(),
+            json!(["single", "say \"hi\"\n", "A"])
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(
+            lua_table_to_json("{\n  a = 1\n  b = 2 }"),
+            Err("expected ',' or '}' on line 3".to_owned())
+        );
+        assert!(lua_table_to_json("{ a = function() end }").is_err());
+        assert!(lua_table_to_json("{ \"unterminated }").is_err());
+    }
+}
@@ -1,29 +1,35 @@
 use crate::constants::FONT_SIZE;
-use crate::actions::Action;
+use crate::bindings::KeyBindings;
 use crate::components::AwaitingInputState;
-use crate::components::BlocksMovement;
 use crate::components::Direction;
 use crate::components::EntityMovingState;
-use crate::components::FacingDirection;
 use crate::components::GridPosition;
-use crate::components::Player;
 use crate::components::PlayerEntity;
-use crate::components::SpriteDrawable;
-use crate::components::Strolling;
-use crate::components::TriggerActionOnEnter;
-use crate::components::TriggerActionOnExit;
-use crate::components::TriggerActionOnUse;
+use crate::constants::DIALOG_TREE_FILES;
+use crate::constants::LANGUAGES;
+use crate::constants::RECORDING_VERSION;
+use crate::constants::STARTING_LEVEL;
 use crate::constants::UI_TEXTURE_CARET;
+use crate::dialogs::DialogTrees;
 use crate::events::EventQueue;
-use crate::map::GameMap;
+use crate::game::create_world;
+use crate::game::finish_frame;
+use crate::gamepad::GamepadProfiles;
+use crate::gamepad::Gamepads;
+use crate::level::spawn_level_entities;
+use crate::localization::Localization;
 use crate::megaui::Style;
-use crate::systems::ActionSystem;
-use crate::systems::CharacterMovingSystem;
-use crate::systems::InputSystem;
-use crate::systems::PlanStrollSystem;
+use crate::platform::seed_from_time;
+use crate::platform::Input;
+use crate::platform::Rng;
+use crate::platform::Time;
+use crate::recording::Recorder;
+use crate::recording::RecordingHeader;
+use crate::recording::Replay;
+use crate::recording::ReplayState;
+use crate::systems::simulation_dispatcher_builder;
 use crate::systems::RenderingSystem;
-use crate::systems::UiSystem;
-use crate::ui::DialogBoxConf;
+use crate::systems::UiRenderingSystem;
 use crate::ui::UiState;
 use macroquad::prelude::*;
 use megaui::Color;
@@ -35,19 +41,60 @@ use megaui_macroquad::{
     megaui::{self},
     set_font_atlas,
 };
-use specs::DispatcherBuilder;
-use specs::{Builder, World, WorldExt};
+use specs::WorldExt;
 
 mod actions;
+mod bindings;
+mod camera;
+mod clock;
 mod components;
 mod constants;
+mod controls_menu;
+mod dialogs;
 mod events;
+mod fonts;
+mod game;
+mod gamepad;
+mod level;
+mod localization;
+mod lua_table;
 mod map;
+mod markup;
+mod pathfinding;
+mod platform;
+mod prefabs;
+mod recording;
+mod save;
+#[cfg(test)]
+mod simulation;
 mod systems;
 mod text;
 mod ui;
 mod util;
 
+// command line options, e.g. `cargo run -- larger_map --record session.jsonl`
+#[derive(Default)]
+struct LaunchOptions {
+    level_id: Option<String>,
+    record: Option<String>, // write an input recording to this file, see recording.rs
+    replay: Option<String>, // play back the input recording in this file, then carry on with live input
+}
+
+fn parse_args() -> LaunchOptions {
+    let mut options = LaunchOptions {
+        ..Default::default()
+    };
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--record" => options.record = args.next(),
+            "--replay" => options.replay = args.next(),
+            _ => options.level_id = Some(arg),
+        }
+    }
+    options
+}
+
 fn window_conf() -> Conf {
     Conf {
         window_title: "RPG Explore".to_owned(),
@@ -63,11 +110,16 @@ async fn main() {
     let texture = load_texture("assets/texture/continue_caret.png").await;
     set_megaui_texture(UI_TEXTURE_CARET, texture);
 
+    // load text data first, since the font atlas needs to know which characters it uses
+    let localization = Localization::load(LANGUAGES).await;
+    let dialog_trees = DialogTrees::load(DIALOG_TREE_FILES).await;
+    localization.check_keys(&dialog_trees.string_keys());
+
     // setup UI style
-    let font_bytes = &include_bytes!("../assets/fonts/Roboto-Bold.ttf")[..];
-    let font_atlas =
-        FontAtlas::new(font_bytes, FONT_SIZE, FontAtlas::ascii_character_list()).unwrap();
-    set_font_atlas(font_atlas);
+    let mut font_characters = fonts::base_characters();
+    font_characters.extend(localization.characters());
+    let font = fonts::load_font(&font_characters);
+    set_font_atlas(font.build_atlas());
     set_ui_style(Style {
         title_height: 32.,
         margin: 5.,
@@ -77,178 +129,100 @@ async fn main() {
         ..Default::default()
     });
     // need to recreate font_atlas that got moved above, so we can use it below
-    let font_atlas =
-        FontAtlas::new(font_bytes, FONT_SIZE, FontAtlas::ascii_character_list()).unwrap();
+    let font_atlas = font.build_atlas();
 
-    // Setup specs world
-    let mut world = World::new();
-    // We only need to explicitly register the components used by Player entity, the rest get setup by dispatcher.setup(...)
-    world.register::<Player>();
-    world.register::<BlocksMovement>();
-    world.register::<GridPosition>();
-    world.register::<SpriteDrawable>();
-    world.register::<FacingDirection>();
-    world.register::<AwaitingInputState>();
-
-    // Create player entity
-    let character_texture = load_texture("assets/texture/walk_cycle.png").await;
-    let player_entity = world
-        .create_entity()
-        .with(Player {})
-        .with(BlocksMovement {})
-        // .with(GridPosition { x: 9., y: 3. })
-        .with(GridPosition { x: 2., y: 4. })
-        .with(SpriteDrawable {
-            texture: character_texture,
-            tile_width: 16.,
-            tile_height: 24.,
-            row: 0.,
-            current_frame: 8.,
-        })
-        .with(FacingDirection {
-            direction: Direction::Down,
-        })
-        .with(AwaitingInputState {})
-        .build();
-
-    // Insert global resources
-    let map = GameMap::new().await;
-    world.insert(map);
-    world.insert(EventQueue {
-        ..Default::default()
+    // a replay decides the level, seed and controls, otherwise the level can be chosen on the command line
+    let options = parse_args();
+    let mut replay = options.replay.map(|path| {
+        Replay::load(&path).unwrap_or_else(|err| panic!("failed to load replay {}: {}", path, err))
     });
-    world.insert(PlayerEntity {
-        entity: player_entity,
+    let gamepad_profiles = GamepadProfiles::load().await;
+    let key_bindings = match &replay {
+        Some(replay) => {
+            KeyBindings::from_file(&replay.header.key_bindings, replay.header.gamepad.clone())
+                .expect("bindings were checked when loading the replay")
+        }
+        None => KeyBindings::load(&gamepad_profiles).await,
+    };
+    let header = match &replay {
+        Some(replay) => replay.header.clone(),
+        None => RecordingHeader {
+            version: RECORDING_VERSION,
+            level_id: options
+                .level_id
+                .unwrap_or_else(|| STARTING_LEVEL.to_owned()),
+            seed: seed_from_time(),
+            key_bindings: key_bindings.to_file(),
+            gamepad: key_bindings.gamepad.clone(),
+        },
+    };
+    let mut recorder = options.record.map(|path| {
+        Recorder::create(&path, &header)
+            .unwrap_or_else(|err| panic!("failed to create recording {}: {}", path, err))
     });
-    world.insert(UiState {
+
+    // Setup specs world
+    let mut world = create_world(
+        &header.level_id,
         font_atlas,
-        dialog_box: None,
-    });
+        localization,
+        dialog_trees,
+        Rng::new(header.seed),
+        key_bindings,
+        gamepad_profiles,
+    )
+    .await;
 
     // Dispatcher setup will register all systems and do other setup
-    let mut dispatcher = DispatcherBuilder::new()
-        .with(InputSystem, "input", &[])
-        .with(PlanStrollSystem, "plan_stroll", &[])
-        .with(
-            CharacterMovingSystem,
-            "character_moving",
-            &["input", "plan_stroll"],
-        )
-        .with(ActionSystem, "action", &[])
+    let mut dispatcher = simulation_dispatcher_builder()
         .with(
             RenderingSystem {
                 ..Default::default()
             },
             "rendering",
-            &[],
+            &["camera"],
         )
-        .with(UiSystem, "ui", &["rendering"])
+        .with(UiRenderingSystem, "ui_rendering", &["ui", "rendering"])
         .build();
     dispatcher.setup(&mut world);
 
-    // Create non-player entities
-
-    // Top door
-    world
-        .create_entity()
-        .with(GridPosition { x: 11., y: 2. })
-        .with(TriggerActionOnEnter {
-            action: Action::Teleport(GridPosition { x: 10., y: 11. }),
-        })
-        .build();
-    // Bottom door
-    world
-        .create_entity()
-        .with(GridPosition { x: 10., y: 12. })
-        .with(TriggerActionOnEnter {
-            action: Action::Teleport(GridPosition { x: 11., y: 3. }),
-        })
-        .build();
-    // Starting position
-    world
-        .create_entity()
-        .with(GridPosition { x: 9., y: 3. })
-        .with(TriggerActionOnExit {
-            action: Action::PrintMessage("left start position".to_owned()),
-        })
-        .build();
-    // Useable urn
-    world
-        .create_entity()
-        .with(GridPosition { x: 10., y: 3. })
-        .with(BlocksMovement {})
-        .with(TriggerActionOnUse {
-            action: Action::ShowSimpleDialog("The urn is full of snakes!".to_owned()),
-        })
-        .build();
-    // Banner
-    world
-        .create_entity()
-        .with(GridPosition { x: 8., y: 2. })
-        .with(TriggerActionOnUse {
-            action: Action::ShowSimpleDialog("When, O Catiline, do you mean to cease abusing our patience? How long is that madness of yours still to mock us? When is there to be an end of that unbridled audacity of yours, swaggering about as it does now? Do not the nightly guards placed on the Palatine Hill -- do not the watches posted throughout the city -- does not the alarm of the people, and the union of all good men -- does not the precaution taken of assembling the senate in this most defensible place -- do not the looks and countenances of this venerable body here present, have any effect upon you? Do you not feel that your plans are detected? Do you not see that your conspiracy is already arrested and rendered powerless by the knowledge which every one here possesses of it? What is there that you did last night, what the night before -- where is it that you were -- who was there that you summoned to meet you -- what design was there which was adopted by you, with which you think that any one of us is unacquainted?".to_owned()),
-        })
-        .build();
-    // Standing NPC
-    world
-        .create_entity()
-        .with(GridPosition { x: 3., y: 4. })
-        .with(BlocksMovement {})
-        .with(SpriteDrawable {
-            texture: character_texture,
-            tile_width: 16.,
-            tile_height: 24.,
-            row: 2.,
-            current_frame: 8.,
-        })
-        .with(FacingDirection {
-            direction: Direction::Down,
-        })
-        .with(TriggerActionOnUse {
-            action: Action::ShowDialog(DialogBoxConf {
-                message: "Welcome to town. Do you like it so far?".to_owned(),
-                title: Some("Innkeeper".to_owned()),
-                ..Default::default()
-            }),
-        })
-        .build();
-    // Strolling NPC
-    world
-        .create_entity()
-        .with(GridPosition { x: 6., y: 8. })
-        .with(BlocksMovement {})
-        .with(SpriteDrawable {
-            texture: character_texture,
-            tile_width: 16.,
-            tile_height: 24.,
-            row: 4.,
-            current_frame: 8.,
-        })
-        .with(FacingDirection {
-            direction: Direction::Down,
-        })
-        .with(Strolling {
-            max_pause_seconds: 3.,
-        })
-        .build();
+    // Create non-player entities for the starting level, from its map's object layers
+    spawn_level_entities(&mut world);
 
+    let mut gamepads = Gamepads::connect();
     loop {
         clear_background(BLACK);
 
+        // read this frame's time and input, which systems get from resources rather than macroquad
+        let replayed_frame = replay.as_mut().and_then(|replay| replay.next_frame());
+        if replay.is_some() && replayed_frame.is_none() {
+            println!("Replay finished, back to live input");
+            replay = None;
+        }
+        world.write_resource::<ReplayState>().replaying = replay.is_some();
+        let (delta_seconds, input) = replayed_frame.unwrap_or_else(|| {
+            let dead_zone = world.read_resource::<KeyBindings>().gamepad.dead_zone;
+            (
+                get_frame_time(),
+                Input::from_devices(&mut gamepads, dead_zone),
+            )
+        });
+        let recorded = recorder
+            .as_mut()
+            .map(|recorder| recorder.record(delta_seconds, &input));
+        if let Some(Err(err)) = recorded {
+            println!("WARNING: stopped recording input: {}", err);
+            recorder = None;
+        }
+        world.write_resource::<Time>().advance(delta_seconds);
+        *world.write_resource::<Input>() = input;
+
         // run ECS systems
         dispatcher.dispatch(&world);
         world.maintain();
 
-        // handle events
-        let mut event_queue = world.write_resource::<EventQueue>();
-        if !event_queue.events.is_empty() {
-            println!("current events: {:?}", event_queue.events);
-        }
-        if !event_queue.new_events.is_empty() {
-            println!("new events: {:?}", event_queue.new_events);
-        }
-        event_queue.events = (*event_queue.new_events).to_vec();
-        event_queue.new_events.clear();
+        // level changes, saving and event handling
+        finish_frame(&mut world).await;
 
         draw_megaui();
 
@@ -1,51 +1,543 @@
+use crate::components::GridPosition;
+use crate::level::LevelConf;
+use crate::lua_table::lua_table_to_json;
+use crate::platform::load_texture;
+use flate2::read::GzDecoder;
+use flate2::read::ZlibDecoder;
 use macroquad::prelude::*;
 use macroquad_tiled::load_map;
 use macroquad_tiled::Map;
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::io::Read;
 
 pub struct GameMap {
     pub map: Map,
+    pub level_id: String,
+    pub level: LevelConf,
     pub width: f32,
     pub height: f32,
     pub tile_width: f32,
     pub tile_height: f32,
+    pub objects: Vec<MapObject>, // everything placed on the map's object layers, see level.rs for how they become entities
+    pub tile_animations: HashMap<String, HashMap<u32, TileAnimation>>, // keyed by tileset name, then tile id
+}
+
+// A tile that cycles through other tiles of its tileset, as set up in Tiled's tileset editor (e.g. water or torches)
+#[derive(Debug, Clone, PartialEq)]
+pub struct TileAnimation {
+    pub frames: Vec<AnimationFrame>,
+    pub duration: u32, // of all the frames together, in milliseconds
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct AnimationFrame {
+    #[serde(rename = "tileid")]
+    pub tile_id: u32,
+    pub duration: u32, // in milliseconds, like Tiled
+}
+
+impl TileAnimation {
+    fn new(frames: Vec<AnimationFrame>) -> TileAnimation {
+        let duration = frames.iter().map(|frame| frame.duration).sum();
+        TileAnimation { frames, duration }
+    }
+
+    // which tile to show at the given time (in seconds). every copy of the tile shares the same clock, so they animate in sync.
+    pub fn tile_id_at(&self, time: f64) -> u32 {
+        if self.duration == 0 {
+            return self.frames[0].tile_id;
+        }
+        let mut remaining = ((time * 1000.) as u64 % self.duration as u64) as u32;
+        for frame in self.frames.iter() {
+            if remaining < frame.duration {
+                return frame.tile_id;
+            }
+            remaining -= frame.duration;
+        }
+        self.frames[self.frames.len() - 1].tile_id
+    }
+}
+
+// An object placed in one of the Tiled map's object layers, snapped to the tile grid.
+// Its custom properties are kept as raw JSON values, since Tiled lets each one have its own type.
+#[derive(Debug, Clone)]
+pub struct MapObject {
+    pub id: u32, // unique within the map, assigned by Tiled
+    pub name: String,
+    pub kind: String, // the object's "Type" in Tiled
+    pub position: GridPosition,
+    pub width: f32, // in tiles, e.g. for an area marked out with a rectangle
+    pub height: f32,
+    pub properties: HashMap<String, Value>,
+}
+
+impl MapObject {
+    pub fn property_str(&self, name: &str) -> Option<&str> {
+        self.properties.get(name).and_then(|value| value.as_str())
+    }
+
+    pub fn property_f32(&self, name: &str) -> Option<f32> {
+        self.properties
+            .get(name)
+            .and_then(|value| value.as_f64())
+            .map(|value| value as f32)
+    }
+
+    pub fn property_bool(&self, name: &str) -> bool {
+        self.properties
+            .get(name)
+            .and_then(|value| value.as_bool())
+            .unwrap_or(false)
+    }
+}
+
+#[derive(Deserialize)]
+struct TiledObject {
+    #[serde(default)]
+    id: u32,
+    #[serde(default)]
+    name: String,
+    #[serde(default, rename = "type")]
+    kind: String,
+    x: f32,
+    y: f32,
+    #[serde(default)]
+    width: f32,
+    #[serde(default)]
+    height: f32,
+    #[serde(default)]
+    gid: Option<u32>,
+    #[serde(default)]
+    properties: Vec<TiledProperty>,
+}
+
+#[derive(Deserialize)]
+struct TiledProperty {
+    name: String,
+    value: Value,
+}
+
+#[derive(Deserialize)]
+struct TiledTileset {
+    #[serde(default)]
+    name: String,
+    #[serde(default)]
+    tiles: Vec<TiledTile>,
+}
+
+#[derive(Deserialize)]
+struct TiledTile {
+    id: u32,
+    #[serde(default)]
+    animation: Vec<AnimationFrame>,
 }
 
 impl GameMap {
-    pub async fn new() -> GameMap {
+    // loads the level with the given id (see assets/levels), along with its Tiled map
+    pub async fn load(level_id: &str) -> GameMap {
+        let level = LevelConf::load(level_id).await;
+
         // Load Tiled map definition and necessary textures
-        let map_json_bytes = load_file("assets/maps/small_room.json")
+        let map_json_bytes = load_file(&level.map)
             .await
-            .expect("failed to load small_room.json");
-        let map_json_str =
-            String::from_utf8(map_json_bytes).expect("failed to convert JSON to utf-8");
-        let texture_atlas = load_texture("assets/texture/rpg_indoor.png").await;
-        let collision_atlas = load_texture("assets/texture/collision_graphic.png").await;
-        let map = load_map(
-            &map_json_str,
-            &[
-                ("rpg_indoor.png", texture_atlas),
-                ("collision_graphic.png", collision_atlas),
-            ],
-        )
-        .expect("failed to load map");
+            .unwrap_or_else(|_| panic!("failed to load {}", level.map));
+        // maps can be exported from Tiled as either JSON or Lua
+        let mut map_json: Value = if level.map.ends_with(".lua") {
+            let map_lua_str =
+                String::from_utf8(map_json_bytes).expect("failed to convert Lua to utf-8");
+            lua_table_to_json(&map_lua_str)
+                .unwrap_or_else(|err| panic!("failed to parse {}: {}", level.map, err))
+        } else {
+            serde_json::from_slice(&map_json_bytes).expect("failed to parse map JSON")
+        };
+        normalize_properties(&mut map_json);
+        decode_tile_layers(&mut map_json);
+        let objects = take_map_objects(&mut map_json, level.tile_width, level.tile_height);
+        let tile_animations = read_tile_animations(&map_json);
+        let map_json_str = map_json.to_string();
+        let mut textures = vec![];
+        for tileset in level.tilesets.iter() {
+            textures.push((tileset.image.as_str(), load_texture(&tileset.texture).await));
+        }
+        let map = load_map(&map_json_str, &textures).expect("failed to load map");
         let map_height = map.raw_tiled_map.height as f32;
         let map_width = map.raw_tiled_map.width as f32;
         println!("map height {} and width {}", map_height, map_width);
         GameMap {
             map,
+            level_id: level_id.to_owned(),
             width: map_width,
             height: map_height,
-            tile_width: 16.,
-            tile_height: 16.,
+            tile_width: level.tile_width,
+            tile_height: level.tile_height,
+            level,
+            objects,
+            tile_animations,
+        }
+    }
+
+    // the tile id to draw at the given time (in seconds), which is only different from tile_id for animated tiles
+    pub fn animated_tile_id(&self, tileset: &str, tile_id: u32, time: f64) -> u32 {
+        self.tile_animations
+            .get(tileset)
+            .and_then(|animations| animations.get(&tile_id))
+            .map_or(tile_id, |animation| animation.tile_id_at(time))
+    }
+
+    pub fn is_blocked(&self, grid_x: f32, grid_y: f32, elevation: usize) -> bool {
+        // check the collision layer of the given elevation to see if x/y should be considered blocked
+        let collision_layer = self
+            .level
+            .elevations
+            .get(elevation)
+            .and_then(|elevation| elevation.collision_layer.as_ref());
+        match collision_layer {
+            Some(collision_layer) => self
+                .map
+                .get_tile(collision_layer, grid_x as u32, grid_y as u32)
+                .as_ref()
+                .is_some(),
+            None => false,
+        }
+    }
+}
+
+// removes all object layers from the Tiled map JSON (macroquad-tiled only needs the tile layers) and returns their objects.
+// Tiled positions objects in pixels, so they are converted to grid coordinates here.
+fn take_map_objects(map_json: &mut Value, tile_width: f32, tile_height: f32) -> Vec<MapObject> {
+    let mut objects = vec![];
+    let layers = match map_json
+        .get_mut("layers")
+        .and_then(|layers| layers.as_array_mut())
+    {
+        Some(layers) => layers,
+        None => return objects,
+    };
+    let (object_layers, tile_layers): (Vec<Value>, Vec<Value>) = layers
+        .drain(..)
+        .partition(|layer| layer["type"] == "objectgroup");
+    *layers = tile_layers;
+    for layer in object_layers {
+        let layer_objects: Vec<TiledObject> = serde_json::from_value(layer["objects"].clone())
+            .unwrap_or_else(|err| {
+                panic!("failed to parse object layer {}: {}", layer["name"], err)
+            });
+        for object in layer_objects {
+            // tile objects are anchored at their bottom-left corner, everything else at the top-left
+            let top = match object.gid {
+                Some(_) => object.y - object.height,
+                None => object.y,
+            };
+            objects.push(MapObject {
+                id: object.id,
+                name: object.name,
+                kind: object.kind,
+                position: GridPosition {
+                    x: (object.x / tile_width).floor(),
+                    y: (top / tile_height).floor(),
+                },
+                width: (object.width / tile_width).ceil(),
+                height: (object.height / tile_height).ceil(),
+                properties: object
+                    .properties
+                    .into_iter()
+                    .map(|property| (property.name, property.value))
+                    .collect(),
+            });
+        }
+    }
+    objects
+}
+
+// macroquad-tiled only understands tile layer data as a plain array of tile ids (Tiled's CSV layer format).
+// Tiled can also store it base64-encoded, optionally compressed with zlib or gzip, so those layers are decoded here.
+fn decode_tile_layers(map_json: &mut Value) {
+    let layers = match map_json
+        .get_mut("layers")
+        .and_then(|layers| layers.as_array_mut())
+    {
+        Some(layers) => layers,
+        None => return,
+    };
+    for layer in layers.iter_mut() {
+        match layer["encoding"].as_str() {
+            Some("base64") => {
+                let data = decode_layer_data(layer).unwrap_or_else(|err| {
+                    panic!("failed to decode tile layer {}: {}", layer["name"], err)
+                });
+                layer["data"] = data.into_iter().map(Value::from).collect();
+            }
+            // Lua exports are already an array of tile ids
+            Some("csv") | Some("lua") | None => (),
+            Some(encoding) => panic!(
+                "unknown encoding {} for tile layer {}",
+                encoding, layer["name"]
+            ),
+        }
+        // what's left looks just like a CSV layer in Tiled's JSON export
+        if let Some(layer) = layer.as_object_mut() {
+            layer.remove("encoding");
+            layer.remove("compression");
+        }
+        // group layers have layers of their own
+        decode_tile_layers(layer);
+    }
+}
+
+fn decode_layer_data(layer: &Value) -> Result<Vec<u32>, String> {
+    let encoded = layer["data"]
+        .as_str()
+        .ok_or_else(|| "base64 layer data should be a string".to_owned())?;
+    let bytes = base64::decode(encoded.trim()).map_err(|err| err.to_string())?;
+    let mut decompressed = vec![];
+    match layer["compression"].as_str().unwrap_or("") {
+        "" => decompressed = bytes,
+        "zlib" => {
+            ZlibDecoder::new(&bytes[..])
+                .read_to_end(&mut decompressed)
+                .map_err(|err| err.to_string())?;
         }
+        "gzip" => {
+            GzDecoder::new(&bytes[..])
+                .read_to_end(&mut decompressed)
+                .map_err(|err| err.to_string())?;
+        }
+        compression => {
+            return Err(format!(
+                "unsupported compression {}, change the layer to zlib, gzip or CSV in Tiled",
+                compression
+            ))
+        }
+    }
+    // every tile id is a little-endian u32
+    if decompressed.len() % 4 != 0 {
+        return Err(format!(
+            "{} bytes is not a whole number of tiles",
+            decompressed.len()
+        ));
+    }
+    Ok(decompressed
+        .chunks(4)
+        .map(|id| u32::from_le_bytes([id[0], id[1], id[2], id[3]]))
+        .collect())
+}
+
+// Tiled's JSON export has custom properties as a list of {name, type, value}, but the Lua export has them as a table of name = value.
+// This converts the latter to the former everywhere in the map, so the rest of the code only deals with one format.
+fn normalize_properties(value: &mut Value) {
+    match value {
+        Value::Object(object) => {
+            if let Some(Value::Object(properties)) = object.get("properties") {
+                let properties = properties
+                    .iter()
+                    .map(|(name, value)| {
+                        let property_type = match value {
+                            Value::Bool(_) => "bool",
+                            Value::Number(number) if number.is_f64() => "float",
+                            Value::Number(_) => "int",
+                            _ => "string",
+                        };
+                        serde_json::json!({ "name": name, "type": property_type, "value": value })
+                    })
+                    .collect();
+                object.insert("properties".to_owned(), Value::Array(properties));
+            }
+            object.values_mut().for_each(normalize_properties);
+        }
+        Value::Array(array) => array.iter_mut().for_each(normalize_properties),
+        _ => (),
+    }
+}
+
+// collects the animations set up in the map's tilesets, which macroquad-tiled doesn't know about
+fn read_tile_animations(map_json: &Value) -> HashMap<String, HashMap<u32, TileAnimation>> {
+    let mut tile_animations = HashMap::new();
+    let tilesets: Vec<TiledTileset> = match serde_json::from_value(map_json["tilesets"].clone()) {
+        Ok(tilesets) => tilesets,
+        Err(err) => {
+            println!(
+                "WARNING: failed to read tile animations, tiles won't animate: {}",
+                err
+            );
+            return tile_animations;
+        }
+    };
+    for tileset in tilesets {
+        let animations: HashMap<u32, TileAnimation> = tileset
+            .tiles
+            .into_iter()
+            .filter(|tile| !tile.animation.is_empty())
+            .map(|tile| (tile.id, TileAnimation::new(tile.animation)))
+            .collect();
+        if !animations.is_empty() {
+            tile_animations.insert(tileset.name, animations);
+        }
+    }
+    tile_animations
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use flate2::write::GzEncoder;
+    use flate2::write::ZlibEncoder;
+    use flate2::Compression;
+    use std::io::Write;
+
+    #[test]
+    fn test_take_map_objects() {
+        let mut map_json = serde_json::json!({
+            "layers": [
+                { "type": "tilelayer", "name": "ground", "data": [1, 2, 3, 4] },
+                {
+                    "type": "objectgroup",
+                    "name": "objects",
+                    "objects": [
+                        {
+                            "id": 4,
+                            "name": "Urn",
+                            "type": "prop",
+                            "x": 160, "y": 48, "width": 16, "height": 16,
+                            "properties": [
+                                { "name": "blocks", "type": "bool", "value": true },
+                                { "name": "dialog", "type": "string", "value": "urn.snakes" }
+                            ]
+                        },
+                        { "name": "Tile", "x": 32, "y": 64, "width": 16, "height": 16, "gid": 5 }
+                    ]
+                }
+            ]
+        });
+        let objects = take_map_objects(&mut map_json, 16., 16.);
+
+        // only the tile layer is left for macroquad-tiled
+        assert_eq!(map_json["layers"].as_array().unwrap().len(), 1);
+        assert_eq!(map_json["layers"][0]["name"], "ground");
+
+        assert_eq!(objects.len(), 2);
+        assert_eq!(objects[0].id, 4);
+        assert_eq!(objects[0].kind, "prop");
+        assert_eq!(objects[0].position, GridPosition { x: 10., y: 3. });
+        assert_eq!((objects[0].width, objects[0].height), (1., 1.));
+        assert!(objects[0].property_bool("blocks"));
+        assert_eq!(objects[0].property_str("dialog"), Some("urn.snakes"));
+        assert_eq!(objects[0].property_f32("dialog"), None);
+        assert!(!objects[0].property_bool("missing"));
+        assert_eq!(objects[1].kind, "");
+        assert_eq!(objects[1].position, GridPosition { x: 2., y: 3. });
+    }
+
+    fn encode_tile_ids(ids: &[u32]) -> Vec<u8> {
+        ids.iter()
+            .flat_map(|id| id.to_le_bytes().to_vec())
+            .collect()
+    }
+
+    #[test]
+    fn test_decode_tile_layers() {
+        let ids = [1, 2, 0, 0x8000_0005];
+        let raw = encode_tile_ids(&ids);
+        let mut zlib = ZlibEncoder::new(vec![], Compression::default());
+        zlib.write_all(&raw).unwrap();
+        let mut gzip = GzEncoder::new(vec![], Compression::default());
+        gzip.write_all(&raw).unwrap();
+        let mut map_json = serde_json::json!({
+            "layers": [
+                { "name": "csv", "data": [1, 2, 0, 2147483653u32] },
+                { "name": "base64", "encoding": "base64", "data": base64::encode(&raw) },
+                { "name": "zlib", "encoding": "base64", "compression": "zlib", "data": base64::encode(zlib.finish().unwrap()) },
+                {
+                    "name": "group",
+                    "type": "group",
+                    "layers": [
+                        { "name": "gzip", "encoding": "base64", "compression": "gzip", "data": base64::encode(gzip.finish().unwrap()) }
+                    ]
+                }
+            ]
+        });
+        decode_tile_layers(&mut map_json);
+
+        let expected = serde_json::json!(ids);
+        for layer in map_json["layers"].as_array().unwrap().iter().take(3) {
+            assert_eq!(layer["data"], expected, "layer {}", layer["name"]);
+            assert!(layer.get("encoding").is_none());
+            assert!(layer.get("compression").is_none());
+        }
+        assert_eq!(map_json["layers"][3]["layers"][0]["data"], expected);
+    }
+
+    #[test]
+    fn test_decode_layer_data_errors() {
+        let layer =
+            serde_json::json!({ "encoding": "base64", "compression": "zstd", "data": "AAAAAA==" });
+        assert!(decode_layer_data(&layer).unwrap_err().contains("zstd"));
+        let layer = serde_json::json!({ "encoding": "base64", "data": "AAA=" });
+        assert!(decode_layer_data(&layer).is_err());
+    }
+
+    #[test]
+    fn test_normalize_properties() {
+        let mut map_json = serde_json::json!({
+            "properties": [{ "name": "already", "type": "string", "value": "a list" }],
+            "layers": [{
+                "objects": [{
+                    "properties": { "blocks": true, "dialog": "urn.snakes", "sprite_row": 2, "stroll_pause": 1.5 }
+                }]
+            }]
+        });
+        normalize_properties(&mut map_json);
+        assert_eq!(map_json["properties"][0]["value"], "a list");
+        let mut properties = map_json["layers"][0]["objects"][0]["properties"]
+            .as_array()
+            .unwrap()
+            .clone();
+        properties.sort_by_key(|property| property["name"].as_str().unwrap().to_owned());
+        assert_eq!(
+            properties,
+            vec![
+                serde_json::json!({ "name": "blocks", "type": "bool", "value": true }),
+                serde_json::json!({ "name": "dialog", "type": "string", "value": "urn.snakes" }),
+                serde_json::json!({ "name": "sprite_row", "type": "int", "value": 2 }),
+                serde_json::json!({ "name": "stroll_pause", "type": "float", "value": 1.5 }),
+            ]
+        );
     }
 
-    pub fn is_blocked(&self, grid_x: f32, grid_y: f32) -> bool {
-        // check the collision layer of the map to see if x/y should be considered blocked
-        // TODO: right now this hard-codes the 'layer' 1 collision, but in the future we could pass in the Player's current layer (e.g. if we have multi-level maps with ladders and such)
-        self.map
-            .get_tile("collision1", grid_x as u32, grid_y as u32)
-            .as_ref()
-            .is_some()
+    #[test]
+    fn test_tile_animations() {
+        let map_json = serde_json::json!({
+            "tilesets": [
+                {
+                    "name": "water",
+                    "tiles": [
+                        {
+                            "id": 4,
+                            "animation": [
+                                { "tileid": 4, "duration": 100 },
+                                { "tileid": 5, "duration": 300 },
+                                { "tileid": 6, "duration": 100 }
+                            ]
+                        },
+                        { "id": 7, "properties": [] }
+                    ]
+                },
+                { "name": "static" }
+            ]
+        });
+        let tile_animations = read_tile_animations(&map_json);
+        assert_eq!(tile_animations.len(), 1);
+        let animation = &tile_animations["water"][&4];
+        assert_eq!(animation.duration, 500);
+        assert_eq!(animation.tile_id_at(0.), 4);
+        assert_eq!(animation.tile_id_at(0.099), 4);
+        assert_eq!(animation.tile_id_at(0.1), 5);
+        assert_eq!(animation.tile_id_at(0.399), 5);
+        assert_eq!(animation.tile_id_at(0.45), 6);
+        // loops back around
+        assert_eq!(animation.tile_id_at(0.5), 4);
+        assert_eq!(animation.tile_id_at(10.2), 5);
     }
 }
@@ -0,0 +1,208 @@
+use crate::constants::DIALOG_EMPHASIS_COLOR;
+use megaui_macroquad::megaui::Color;
+
+// Dialog text supports a few inline tags, which are stripped out before the text is wrapped:
+// - [color=red]...[/color] draws the enclosed text in a named color (or hex, e.g. [color=#ff8800])
+// - [em]...[/em] draws the enclosed text in the emphasis color, for item names and key words
+// - [pause=0.5] pauses the typewriter reveal for the given number of seconds
+// - [player] is replaced by the player's name
+// Anything else in square brackets is left in the text as-is.
+//
+// Wrapping only ever changes whitespace, so styles are positioned by "visible" character index,
+// i.e. how many non-whitespace characters come before them. That way they still line up after wrapping and chunking.
+#[derive(Debug, Default, PartialEq)]
+pub struct MarkupText {
+    pub text: String, // plain text with all tags removed
+    pub color_spans: Vec<ColorSpan>,
+    pub pauses: Vec<Pause>,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct ColorSpan {
+    pub start: usize, // visible index of first character in span
+    pub end: usize,   // visible index one past the last character in span
+    pub color: Color,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Pause {
+    pub at: usize, // visible index of the character the reveal pauses before
+    pub seconds: f32,
+}
+
+impl MarkupText {
+    // returns the color for the given visible character index, or None if it should use the default text color.
+    // spans can be nested, so the innermost (latest starting) span wins.
+    pub fn color_at(&self, visible_index: usize) -> Option<Color> {
+        self.color_spans
+            .iter()
+            .filter(|span| span.start <= visible_index && visible_index < span.end)
+            .max_by_key(|span| span.start)
+            .map(|span| span.color)
+    }
+}
+
+pub fn parse_markup(source: &str, player_name: &str) -> MarkupText {
+    let mut markup = MarkupText {
+        ..Default::default()
+    };
+    let mut visible_count = 0;
+    // currently open color tags: (tag name, start index, color)
+    let mut open_spans: Vec<(&str, usize, Color)> = vec![];
+
+    let mut rest = source;
+    while let Some(tag_start) = rest.find('[') {
+        push_text(&mut markup.text, &mut visible_count, &rest[..tag_start]);
+        rest = &rest[tag_start..];
+        let tag_end = match rest.find(']') {
+            Some(tag_end) => tag_end,
+            None => break, // no more complete tags, so the remainder is plain text
+        };
+        let tag = &rest[1..tag_end];
+        let mut handled = true;
+        if tag == "player" {
+            push_text(&mut markup.text, &mut visible_count, player_name);
+        } else if tag == "em" {
+            let color = parse_color(DIALOG_EMPHASIS_COLOR).expect("invalid emphasis color");
+            open_spans.push(("em", visible_count, color));
+        } else if let Some(color) = tag.strip_prefix("color=").and_then(parse_color) {
+            open_spans.push(("color", visible_count, color));
+        } else if let Some(seconds) = tag
+            .strip_prefix("pause=")
+            .and_then(|seconds| seconds.parse::<f32>().ok())
+        {
+            markup.pauses.push(Pause {
+                at: visible_count,
+                seconds,
+            });
+        } else if let Some(closing) = tag.strip_prefix('/') {
+            // close the most recent matching tag
+            match open_spans.iter().rposition(|(name, _, _)| *name == closing) {
+                Some(index) => {
+                    let (_, start, color) = open_spans.remove(index);
+                    markup.color_spans.push(ColorSpan {
+                        start,
+                        end: visible_count,
+                        color,
+                    });
+                }
+                None => handled = false,
+            }
+        } else {
+            handled = false;
+        }
+        if !handled {
+            println!("WARNING: unknown markup tag [{}] in text: {}", tag, source);
+            push_text(&mut markup.text, &mut visible_count, &rest[..=tag_end]);
+        }
+        rest = &rest[tag_end + 1..];
+    }
+    push_text(&mut markup.text, &mut visible_count, rest);
+
+    // any tags left open just run to the end of the text
+    for (_, start, color) in open_spans {
+        markup.color_spans.push(ColorSpan {
+            start,
+            end: visible_count,
+            color,
+        });
+    }
+    markup
+}
+
+fn push_text(text: &mut String, visible_count: &mut usize, to_push: &str) {
+    text.push_str(to_push);
+    *visible_count += to_push.chars().filter(|c| !c.is_whitespace()).count();
+}
+
+fn parse_color(name: &str) -> Option<Color> {
+    if let Some(hex) = name.strip_prefix('#') {
+        if hex.len() != 6 {
+            return None;
+        }
+        let value = u32::from_str_radix(hex, 16).ok()?;
+        return Some(Color::from_rgb(
+            (value >> 16) as u8,
+            (value >> 8) as u8,
+            value as u8,
+        ));
+    }
+    match name {
+        "red" => Some(Color::from_rgb(230, 60, 60)),
+        "green" => Some(Color::from_rgb(80, 220, 80)),
+        "blue" => Some(Color::from_rgb(100, 150, 255)),
+        "yellow" => Some(Color::from_rgb(255, 230, 80)),
+        "gold" => Some(Color::from_rgb(255, 200, 40)),
+        "gray" => Some(Color::from_rgb(160, 160, 160)),
+        "white" => Some(Color::from_rgb(255, 255, 255)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_plain_text() {
+        let markup = parse_markup("Nothing special here.", "Rob");
+        assert_eq!(markup.text, "Nothing special here.");
+        assert!(markup.color_spans.is_empty());
+        assert!(markup.pauses.is_empty());
+    }
+
+    #[test]
+    fn test_player_name() {
+        assert_eq!(parse_markup("Hello, [player]!", "Rob").text, "Hello, Rob!");
+    }
+
+    #[test]
+    fn test_color_spans() {
+        let markup = parse_markup(
+            "Take the [color=red]Ruby Sword[/color] and [em]run[/em].",
+            "Rob",
+        );
+        assert_eq!(markup.text, "Take the Ruby Sword and run.");
+        // visible indices skip whitespace: "Takethe" is 7 characters, "RubySword" is 9
+        assert_eq!(
+            markup.color_spans,
+            vec![
+                ColorSpan {
+                    start: 7,
+                    end: 16,
+                    color: Color::from_rgb(230, 60, 60)
+                },
+                ColorSpan {
+                    start: 19,
+                    end: 22,
+                    color: parse_color(DIALOG_EMPHASIS_COLOR).unwrap()
+                },
+            ]
+        );
+        assert_eq!(markup.color_at(6), None);
+        assert_eq!(markup.color_at(7), Some(Color::from_rgb(230, 60, 60)));
+        assert_eq!(markup.color_at(16), None);
+    }
+
+    #[test]
+    fn test_pauses() {
+        let markup = parse_markup("Wait...[pause=1.5] for it.", "Rob");
+        assert_eq!(markup.text, "Wait... for it.");
+        assert_eq!(
+            markup.pauses,
+            vec![Pause {
+                at: 7,
+                seconds: 1.5
+            }]
+        );
+    }
+
+    #[test]
+    fn test_unknown_tags_are_kept() {
+        assert_eq!(
+            parse_markup("[wat] and [/em] [oops", "Rob").text,
+            "[wat] and [/em] [oops"
+        );
+    }
+}
@@ -0,0 +1,311 @@
+use crate::components::BlocksMovement;
+use crate::components::Direction;
+use crate::components::Elevation;
+use crate::components::EntityMovingState;
+use crate::components::GridPosition;
+use crate::map::GameMap;
+use specs::Entities;
+use specs::Entity;
+use specs::Join;
+use specs::ReadStorage;
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::collections::HashMap;
+use std::collections::HashSet;
+
+// A* over the map grid. Tiles are (x, y) pairs of whole grid coordinates, and since characters move one tile at a time
+// in one of four directions, so do paths: no diagonals, and every step costs the same.
+
+// global resource for finding paths around the current level. the map's collision layers never change, so they're read
+// once when the level loads. other entities move around, so the tiles they're on are passed in (see occupied_tiles).
+#[derive(Debug, Default)]
+pub struct Pathfinder {
+    width: i32,
+    height: i32,
+    blocked: Vec<HashSet<(i32, i32)>>, // tiles on each elevation's collision layer
+}
+
+impl Pathfinder {
+    pub fn new(map: &GameMap) -> Pathfinder {
+        let width = map.width as i32;
+        let height = map.height as i32;
+        let blocked = (0..map.level.elevations.len())
+            .map(|elevation| {
+                let mut blocked = HashSet::new();
+                for x in 0..width {
+                    for y in 0..height {
+                        if map.is_blocked(x as f32, y as f32, elevation) {
+                            blocked.insert((x, y));
+                        }
+                    }
+                }
+                blocked
+            })
+            .collect();
+        Pathfinder {
+            width,
+            height,
+            blocked,
+        }
+    }
+
+    // whether the tile is off the map, on the elevation's collision layer, or occupied
+    pub fn is_blocked(
+        &self,
+        tile: (i32, i32),
+        elevation: usize,
+        occupied: &HashSet<(i32, i32)>,
+    ) -> bool {
+        tile.0 < 0
+            || tile.1 < 0
+            || tile.0 >= self.width
+            || tile.1 >= self.height
+            || self
+                .blocked
+                .get(elevation)
+                .map_or(false, |blocked| blocked.contains(&tile))
+            || occupied.contains(&tile)
+    }
+
+    // the positions to step onto to get from one position to another, see find_path
+    pub fn find_path(
+        &self,
+        from: &GridPosition,
+        to: &GridPosition,
+        next_to_goal: bool,
+        elevation: usize,
+        occupied: &HashSet<(i32, i32)>,
+    ) -> Option<Vec<GridPosition>> {
+        let steps = find_path(tile_of(from), tile_of(to), next_to_goal, |x, y| {
+            self.is_blocked((x, y), elevation, occupied)
+        })?;
+        Some(
+            steps
+                .into_iter()
+                .map(|(x, y)| GridPosition {
+                    x: x as f32,
+                    y: y as f32,
+                })
+                .collect(),
+        )
+    }
+}
+
+// the tile an entity is on, rounding off any movement in progress
+pub fn tile_of(position: &GridPosition) -> (i32, i32) {
+    (position.x.round() as i32, position.y.round() as i32)
+}
+
+// tiles on the given elevation where something that blocks movement is, or is on its way to. the entity itself doesn't count.
+pub fn occupied_tiles(
+    except: Entity,
+    elevation: usize,
+    entities: &Entities,
+    positions: &ReadStorage<GridPosition>,
+    elevations: &ReadStorage<Elevation>,
+    blocks_movement: &ReadStorage<BlocksMovement>,
+    entity_moving_states: &ReadStorage<EntityMovingState>,
+) -> HashSet<(i32, i32)> {
+    let mut occupied = HashSet::new();
+    for (entity, position, _, other_elevation, moving_state) in (
+        entities,
+        positions,
+        blocks_movement,
+        elevations.maybe(),
+        entity_moving_states.maybe(),
+    )
+        .join()
+    {
+        if entity == except || Elevation::index_of(other_elevation) != elevation {
+            continue;
+        }
+        occupied.insert(tile_of(position));
+        if let Some(moving_state) = moving_state {
+            occupied.insert(tile_of(&GridPosition {
+                x: position.x + moving_state.delta_x,
+                y: position.y + moving_state.delta_y,
+            }));
+        }
+    }
+    occupied
+}
+
+// steps to neighbouring tiles, in the order they're tried. keeping this fixed means the same map always gives the same path.
+const NEIGHBOURS: [(i32, i32); 4] = [(0, -1), (-1, 0), (0, 1), (1, 0)];
+
+// finds the shortest path from start to goal, as the tiles to step onto in order (not including start).
+// with next_to_goal, the path ends on a tile beside the goal instead, e.g. to use whatever is on it.
+// is_blocked has to say every tile outside the map is blocked, otherwise an unreachable goal searches forever.
+// None if there's no way there.
+pub fn find_path<F: Fn(i32, i32) -> bool>(
+    start: (i32, i32),
+    goal: (i32, i32),
+    next_to_goal: bool,
+    is_blocked: F,
+) -> Option<Vec<(i32, i32)>> {
+    let distance = |(x, y): (i32, i32)| (x - goal.0).abs() + (y - goal.1).abs();
+    let is_done = |tile: (i32, i32)| {
+        if next_to_goal {
+            distance(tile) == 1
+        } else {
+            tile == goal
+        }
+    };
+    if is_done(start) {
+        return Some(vec![]);
+    }
+    if !next_to_goal && is_blocked(goal.0, goal.1) {
+        return None;
+    }
+
+    let mut costs: HashMap<(i32, i32), i32> = HashMap::new();
+    let mut came_from: HashMap<(i32, i32), (i32, i32)> = HashMap::new();
+    // ordered by estimated total cost, then by when the tile was found, so ties always go the same way
+    let mut open = BinaryHeap::new();
+    let mut found = 0;
+    costs.insert(start, 0);
+    open.push(Reverse((distance(start), found, start)));
+    while let Some(Reverse((_, _, tile))) = open.pop() {
+        if is_done(tile) {
+            let mut path = vec![tile];
+            let mut previous = tile;
+            while let Some(&before) = came_from.get(&previous) {
+                if before == start {
+                    break;
+                }
+                path.push(before);
+                previous = before;
+            }
+            path.reverse();
+            return Some(path);
+        }
+        let next_cost = costs[&tile] + 1;
+        for (delta_x, delta_y) in NEIGHBOURS.iter() {
+            let next = (tile.0 + delta_x, tile.1 + delta_y);
+            if is_blocked(next.0, next.1) {
+                continue;
+            }
+            if costs.get(&next).map_or(true, |&cost| next_cost < cost) {
+                costs.insert(next, next_cost);
+                came_from.insert(next, tile);
+                found += 1;
+                open.push(Reverse((next_cost + distance(next), found, next)));
+            }
+        }
+    }
+    None
+}
+
+// which way to step to get from one tile to a neighbouring one
+pub fn direction_to(from: (i32, i32), to: (i32, i32)) -> Option<Direction> {
+    match (to.0 - from.0, to.1 - from.1) {
+        (0, -1) => Some(Direction::Up),
+        (-1, 0) => Some(Direction::Left),
+        (0, 1) => Some(Direction::Down),
+        (1, 0) => Some(Direction::Right),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    // '#' is blocked, anything else is open. everything outside the rows is blocked too.
+    fn grid(rows: &[&str]) -> impl Fn(i32, i32) -> bool {
+        let rows: Vec<Vec<char>> = rows.iter().map(|row| row.chars().collect()).collect();
+        move |x, y| {
+            if x < 0 || y < 0 {
+                return true;
+            }
+            rows.get(y as usize)
+                .and_then(|row| row.get(x as usize))
+                .map_or(true, |tile| *tile == '#')
+        }
+    }
+
+    #[test]
+    fn test_straight_path() {
+        let is_blocked = grid(&["....", "....", "...."]);
+        assert_eq!(
+            find_path((0, 1), (3, 1), false, &is_blocked),
+            Some(vec![(1, 1), (2, 1), (3, 1)])
+        );
+        assert_eq!(find_path((2, 2), (2, 2), false, &is_blocked), Some(vec![]));
+    }
+
+    #[test]
+    fn test_path_around_wall() {
+        let is_blocked = grid(&[
+            "....", //
+            ".##.", //
+            "..#.", //
+        ]);
+        let path = find_path((1, 2), (3, 2), false, &is_blocked).unwrap();
+        assert_eq!(path.len(), 8);
+        assert_eq!(path.last(), Some(&(3, 2)));
+        // every step is onto an open neighbour
+        let mut previous = (1, 2);
+        for tile in path {
+            assert!(!is_blocked(tile.0, tile.1));
+            assert!(direction_to(previous, tile).is_some());
+            previous = tile;
+        }
+    }
+
+    #[test]
+    fn test_unreachable() {
+        let is_blocked = grid(&[
+            "..#.", //
+            "..#.", //
+        ]);
+        assert_eq!(find_path((0, 0), (3, 0), false, &is_blocked), None);
+        // blocked goals can't be reached, only walked up to
+        assert_eq!(find_path((0, 0), (2, 1), false, &is_blocked), None);
+        assert_eq!(
+            find_path((0, 0), (2, 1), true, &is_blocked),
+            Some(vec![(0, 1), (1, 1)])
+        );
+    }
+
+    #[test]
+    fn test_pathfinder() {
+        // a 4x3 map with a wall down the middle and a gap at the bottom
+        let mut wall = HashSet::new();
+        wall.insert((2, 0));
+        wall.insert((2, 1));
+        let pathfinder = Pathfinder {
+            width: 4,
+            height: 3,
+            blocked: vec![wall],
+        };
+        let from = GridPosition { x: 1., y: 0. };
+        let to = GridPosition { x: 3., y: 0. };
+        let path = pathfinder.find_path(&from, &to, false, 0, &HashSet::new());
+        assert_eq!(path.map(|path| path.len()), Some(6));
+
+        // an NPC standing in the gap blocks the only way through
+        let mut occupied = HashSet::new();
+        occupied.insert((2, 2));
+        assert_eq!(pathfinder.find_path(&from, &to, false, 0, &occupied), None);
+        // upstairs has no collision layer, so it's a straight line
+        let path = pathfinder.find_path(&from, &to, false, 1, &occupied);
+        assert_eq!(
+            path,
+            Some(vec![
+                GridPosition { x: 2., y: 0. },
+                GridPosition { x: 3., y: 0. }
+            ])
+        );
+        assert!(pathfinder.is_blocked((4, 0), 1, &occupied));
+    }
+
+    #[test]
+    fn test_direction_to() {
+        assert_eq!(direction_to((3, 3), (3, 2)), Some(Direction::Up));
+        assert_eq!(direction_to((3, 3), (4, 3)), Some(Direction::Right));
+        assert_eq!(direction_to((3, 3), (4, 4)), None);
+        assert_eq!(direction_to((3, 3), (3, 3)), None);
+    }
+}
@@ -0,0 +1,355 @@
+use crate::gamepad::GamepadButton;
+use crate::gamepad::Gamepads;
+use macroquad::input::is_key_down;
+use macroquad::input::is_key_pressed;
+use macroquad::input::is_mouse_button_pressed;
+use macroquad::input::mouse_position;
+use macroquad::input::MouseButton;
+use macroquad::prelude::KeyCode;
+use macroquad::texture::Texture2D;
+use std::collections::HashSet;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+
+// Systems never read the clock, keyboard, gamepads or random numbers straight from macroquad (or gilrs). Instead the main loop fills in the
+// Time and Input resources at the start of every frame, and systems draw random numbers from the Rng resource.
+// That way a headless simulation (see simulation.rs) can script all three, and run the game without a window.
+
+// every key the game can read (and so the keys that controls can be bound to), since macroquad can only be asked about one key at a time.
+// the names are used wherever keys are written to a file, e.g. the controls config and input recordings.
+const GAME_KEYS: &[(&str, KeyCode)] = &[
+    ("Left", KeyCode::Left),
+    ("Right", KeyCode::Right),
+    ("Up", KeyCode::Up),
+    ("Down", KeyCode::Down),
+    ("A", KeyCode::A),
+    ("B", KeyCode::B),
+    ("C", KeyCode::C),
+    ("D", KeyCode::D),
+    ("E", KeyCode::E),
+    ("F", KeyCode::F),
+    ("G", KeyCode::G),
+    ("H", KeyCode::H),
+    ("I", KeyCode::I),
+    ("J", KeyCode::J),
+    ("K", KeyCode::K),
+    ("L", KeyCode::L),
+    ("M", KeyCode::M),
+    ("N", KeyCode::N),
+    ("O", KeyCode::O),
+    ("P", KeyCode::P),
+    ("Q", KeyCode::Q),
+    ("R", KeyCode::R),
+    ("S", KeyCode::S),
+    ("T", KeyCode::T),
+    ("U", KeyCode::U),
+    ("V", KeyCode::V),
+    ("W", KeyCode::W),
+    ("X", KeyCode::X),
+    ("Y", KeyCode::Y),
+    ("Z", KeyCode::Z),
+    ("0", KeyCode::Key0),
+    ("1", KeyCode::Key1),
+    ("2", KeyCode::Key2),
+    ("3", KeyCode::Key3),
+    ("4", KeyCode::Key4),
+    ("5", KeyCode::Key5),
+    ("6", KeyCode::Key6),
+    ("7", KeyCode::Key7),
+    ("8", KeyCode::Key8),
+    ("9", KeyCode::Key9),
+    ("F1", KeyCode::F1),
+    ("F2", KeyCode::F2),
+    ("F3", KeyCode::F3),
+    ("F4", KeyCode::F4),
+    ("F5", KeyCode::F5),
+    ("F6", KeyCode::F6),
+    ("F7", KeyCode::F7),
+    ("F8", KeyCode::F8),
+    ("F9", KeyCode::F9),
+    ("F10", KeyCode::F10),
+    ("F11", KeyCode::F11),
+    ("F12", KeyCode::F12),
+    ("Space", KeyCode::Space),
+    ("Enter", KeyCode::Enter),
+    ("Escape", KeyCode::Escape),
+    ("Tab", KeyCode::Tab),
+    ("Backspace", KeyCode::Backspace),
+    ("LeftShift", KeyCode::LeftShift),
+    ("RightShift", KeyCode::RightShift),
+    ("LeftControl", KeyCode::LeftControl),
+    ("RightControl", KeyCode::RightControl),
+    ("LeftAlt", KeyCode::LeftAlt),
+    ("RightAlt", KeyCode::RightAlt),
+    ("Comma", KeyCode::Comma),
+    ("Period", KeyCode::Period),
+    ("Slash", KeyCode::Slash),
+    ("Semicolon", KeyCode::Semicolon),
+    ("Minus", KeyCode::Minus),
+    ("Equal", KeyCode::Equal),
+];
+
+pub fn key_name(key: KeyCode) -> &'static str {
+    GAME_KEYS
+        .iter()
+        .find(|(_, game_key)| *game_key == key)
+        .map(|(name, _)| *name)
+        .unwrap_or("?")
+}
+
+pub fn key_from_name(name: &str) -> Option<KeyCode> {
+    GAME_KEYS
+        .iter()
+        .find(|(game_name, _)| *game_name == name)
+        .map(|(_, key)| *key)
+}
+
+// global resource with the time for the current frame
+#[derive(Default, Debug, Copy, Clone)]
+pub struct Time {
+    pub delta_seconds: f32,   // since the previous frame
+    pub elapsed_seconds: f64, // since the game started
+}
+
+impl Time {
+    // moves the clock forward by one frame. the main loop passes in macroquad's frame time, or a recorded one when replaying.
+    pub fn advance(&mut self, delta_seconds: f32) {
+        self.delta_seconds = delta_seconds;
+        self.elapsed_seconds += delta_seconds as f64;
+    }
+}
+
+// global resource with the keyboard, gamepad and mouse state for the current frame
+#[derive(Default, Debug, Clone, PartialEq)]
+pub struct Input {
+    keys_down: HashSet<KeyCode>,
+    keys_pressed: HashSet<KeyCode>, // went down this frame
+    buttons_down: HashSet<GamepadButton>,
+    buttons_pressed: HashSet<GamepadButton>, // went down this frame
+    click: Option<(f32, f32)>, // screen position of a click this frame. macroquad turns taps on touch screens into clicks too.
+}
+
+impl Input {
+    // the keyboard from macroquad, plus the gamepads. the dead zone comes from the player's gamepad profile.
+    pub fn from_devices(gamepads: &mut Gamepads, dead_zone: f32) -> Input {
+        let mut input = Input {
+            ..Default::default()
+        };
+        for &(_, key) in GAME_KEYS {
+            if is_key_down(key) {
+                input.keys_down.insert(key);
+            }
+            if is_key_pressed(key) {
+                input.keys_pressed.insert(key);
+            }
+        }
+        let (buttons_down, buttons_pressed) = gamepads.read(dead_zone);
+        input.buttons_down = buttons_down;
+        input.buttons_pressed = buttons_pressed;
+        if is_mouse_button_pressed(MouseButton::Left) {
+            input.click = Some(mouse_position());
+        }
+        input
+    }
+
+    // the names of the keys that are down, and of those that went down this frame, in GAME_KEYS order
+    pub fn key_names(&self) -> (Vec<&'static str>, Vec<&'static str>) {
+        let names = |keys: &HashSet<KeyCode>| {
+            GAME_KEYS
+                .iter()
+                .filter(|(_, key)| keys.contains(key))
+                .map(|(name, _)| *name)
+                .collect()
+        };
+        (names(&self.keys_down), names(&self.keys_pressed))
+    }
+
+    // the gamepad buttons that are down, and those that went down this frame, sorted
+    pub fn buttons(&self) -> (Vec<GamepadButton>, Vec<GamepadButton>) {
+        let sorted = |buttons: &HashSet<GamepadButton>| {
+            let mut buttons: Vec<GamepadButton> = buttons.iter().copied().collect();
+            buttons.sort();
+            buttons
+        };
+        (sorted(&self.buttons_down), sorted(&self.buttons_pressed))
+    }
+
+    pub fn is_key_down(&self, key: KeyCode) -> bool {
+        self.keys_down.contains(&key)
+    }
+
+    pub fn is_key_pressed(&self, key: KeyCode) -> bool {
+        self.keys_pressed.contains(&key)
+    }
+
+    pub fn click(&self) -> Option<(f32, f32)> {
+        self.click
+    }
+
+    pub fn is_button_down(&self, button: GamepadButton) -> bool {
+        self.buttons_down.contains(&button)
+    }
+
+    pub fn is_button_pressed(&self, button: GamepadButton) -> bool {
+        self.buttons_pressed.contains(&button)
+    }
+
+    // the first key (in GAME_KEYS order) that went down this frame, if any
+    pub fn first_pressed_key(&self) -> Option<KeyCode> {
+        GAME_KEYS
+            .iter()
+            .map(|(_, key)| *key)
+            .find(|key| self.keys_pressed.contains(key))
+    }
+
+    // scripted or replayed input: a key held down since an earlier frame
+    pub fn hold(mut self, key: KeyCode) -> Input {
+        self.keys_down.insert(key);
+        self
+    }
+
+    // scripted or replayed input: a key that went down this frame
+    pub fn press(mut self, key: KeyCode) -> Input {
+        self.keys_down.insert(key);
+        self.keys_pressed.insert(key);
+        self
+    }
+
+    // scripted or replayed input: a gamepad button held down since an earlier frame
+    pub fn hold_button(mut self, button: GamepadButton) -> Input {
+        self.buttons_down.insert(button);
+        self
+    }
+
+    // scripted or replayed input: a gamepad button that went down this frame
+    pub fn press_button(mut self, button: GamepadButton) -> Input {
+        self.buttons_down.insert(button);
+        self.buttons_pressed.insert(button);
+        self
+    }
+
+    // scripted or replayed input: a click (or tap) at the given screen position
+    pub fn click_at(mut self, screen_x: f32, screen_y: f32) -> Input {
+        self.click = Some((screen_x, screen_y));
+        self
+    }
+}
+
+// global resource for random numbers, so strolling NPCs etc. are repeatable given the same seed.
+// this is xorshift64*, which is plenty for game logic.
+#[derive(Debug, Clone)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Rng {
+        // scramble the seed (splitmix64), so similar seeds don't give similar sequences. the state must never be 0.
+        let mut state = seed.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        state = (state ^ (state >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        state = (state ^ (state >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        state ^= state >> 31;
+        Rng {
+            state: if state == 0 { 1 } else { state },
+        }
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        self.state.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    // between 0 and 1, not including 1. f32 has 24 bits of precision, so that's how many random bits are used.
+    fn next_f32(&mut self) -> f32 {
+        (self.next_u64() >> 40) as f32 / (1u64 << 24) as f32
+    }
+
+    // from low up to (but not including) high, like quad_rand::gen_range
+    pub fn gen_range(&mut self, low: i32, high: i32) -> i32 {
+        assert!(low < high, "empty range {}..{}", low, high);
+        low + (self.next_u64() % (high - low) as u64) as i32
+    }
+
+    pub fn gen_range_f32(&mut self, low: f32, high: f32) -> f32 {
+        low + self.next_f32() * (high - low)
+    }
+}
+
+impl Default for Rng {
+    fn default() -> Rng {
+        Rng::new(0)
+    }
+}
+
+// a different seed for every game, unless it's being replayed
+pub fn seed_from_time() -> u64 {
+    macroquad::miniquad::date::now().to_bits()
+}
+
+// set by headless simulations, which have no graphics context to load textures into
+static HEADLESS: AtomicBool = AtomicBool::new(false);
+
+#[cfg(test)]
+pub fn set_headless() {
+    HEADLESS.store(true, Ordering::Relaxed);
+}
+
+// use this rather than macroquad's load_texture, so loading assets also works headless
+pub async fn load_texture(path: &str) -> Texture2D {
+    if HEADLESS.load(Ordering::Relaxed) {
+        return Texture2D::empty();
+    }
+    macroquad::texture::load_texture(path).await
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_rng_is_repeatable() {
+        let mut a = Rng::new(42);
+        let mut b = Rng::new(42);
+        let mut c = Rng::new(43);
+        let rolls_a: Vec<i32> = (0..20).map(|_| a.gen_range(0, 4)).collect();
+        let rolls_b: Vec<i32> = (0..20).map(|_| b.gen_range(0, 4)).collect();
+        let rolls_c: Vec<i32> = (0..20).map(|_| c.gen_range(0, 4)).collect();
+        assert_eq!(rolls_a, rolls_b);
+        assert_ne!(rolls_a, rolls_c);
+        assert!(rolls_a.iter().all(|roll| (0..4).contains(roll)));
+        for _ in 0..100 {
+            let pause = a.gen_range_f32(0., 3.);
+            assert!((0. ..3.).contains(&pause));
+        }
+    }
+
+    #[test]
+    fn test_scripted_input() {
+        let input = Input::default().hold(KeyCode::Right).press(KeyCode::Space);
+        assert!(input.is_key_down(KeyCode::Right));
+        assert!(!input.is_key_pressed(KeyCode::Right));
+        assert!(input.is_key_down(KeyCode::Space));
+        assert!(input.is_key_pressed(KeyCode::Space));
+        assert!(!input.is_key_down(KeyCode::Left));
+        assert_eq!(input.key_names(), (vec!["Right", "Space"], vec!["Space"]));
+        assert_eq!(input.first_pressed_key(), Some(KeyCode::Space));
+        let input = input
+            .hold_button(GamepadButton::StickLeft)
+            .press_button(GamepadButton::South);
+        assert!(input.is_button_down(GamepadButton::StickLeft));
+        assert!(!input.is_button_pressed(GamepadButton::StickLeft));
+        assert!(input.is_button_pressed(GamepadButton::South));
+        assert_eq!(
+            input.buttons(),
+            (
+                vec![GamepadButton::StickLeft, GamepadButton::South],
+                vec![GamepadButton::South]
+            )
+        );
+        assert_eq!(key_from_name(key_name(KeyCode::Key2)), Some(KeyCode::Key2));
+        assert_eq!(key_from_name("Hyper"), None);
+    }
+}
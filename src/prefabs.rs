@@ -0,0 +1,527 @@
+use crate::actions::Action;
+use crate::components::BlocksMovement;
+use crate::components::Direction;
+use crate::components::Elevation;
+use crate::components::FacingDirection;
+use crate::components::GridPosition;
+use crate::components::Patrol;
+use crate::components::ScheduleEntry;
+use crate::components::SpriteDrawable;
+use crate::components::StrollArea;
+use crate::components::Strolling;
+use crate::components::TriggerActionOnDialogChoice;
+use crate::components::TriggerActionOnEnter;
+use crate::components::TriggerActionOnExit;
+use crate::components::TriggerActionOnUse;
+use crate::components::Waypoint;
+use crate::platform::load_texture;
+use macroquad::prelude::*;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::Map;
+use serde_json::Value;
+use specs::Builder;
+use specs::EntityBuilder;
+use std::collections::HashMap;
+use std::fmt;
+
+// A prefab is a named bundle of components, so similar entities (e.g. every villager) are described once in assets/prefabs.json
+// and then placed as many times as needed, with overrides per instance (see spawn_level_entities in level.rs).
+// A bundle is a JSON object of component name to component fields, e.g.
+//   "villager": { "extends": "character", "sprite": { "row": 4 }, "strolling": { "max_pause_seconds": 3 } }
+// "extends" names another prefab to start from. Overrides are merged in field by field, so only what differs needs writing.
+//
+// Components:
+// - sprite: { texture, tile_width, tile_height, row } for a character sprite sheet, see SpriteDrawable
+// - facing: "Up", "Down", "Left" or "Right" (defaults to Down for sprites)
+// - blocks_movement: true or false
+// - strolling: { max_pause_seconds, area, exclude }. area is { x, y, width, height } or { x, y, radius } in tiles, and exclude
+//   is a list of { x, y } tiles to keep off. maps can give the area relative to the object instead (see level.rs).
+// - patrol: { route, schedule }. route is a list of waypoints { x, y, wait_seconds, facing } to walk around in a loop, and
+//   schedule a list of { start_hour, route } to switch routes during the day
+// - elevation: which of the level's elevations the entity is on
+// - trigger_on_enter, trigger_on_exit, trigger_on_use: an action, written the same way as in dialog tree files
+// - trigger_on_dialog_choice: { dialog_id, actions }
+pub type ComponentBundle = Map<String, Value>;
+
+#[derive(Debug, PartialEq)]
+pub enum PrefabError {
+    UnknownPrefab(String),
+    ExtendsCycle(String),
+    // source describes what was being built, e.g. a prefab id or a map object
+    UnknownComponent {
+        source: String,
+        component: String,
+    },
+    BadComponent {
+        source: String,
+        component: String,
+        error: String,
+    },
+    // textures are loaded up front for every prefab, since entities are built outside of async code
+    TextureNotLoaded {
+        source: String,
+        texture: String,
+    },
+}
+
+impl fmt::Display for PrefabError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PrefabError::UnknownPrefab(prefab) => write!(f, "unknown prefab {}", prefab),
+            PrefabError::ExtendsCycle(prefab) => {
+                write!(f, "prefab {} ends up extending itself", prefab)
+            }
+            PrefabError::UnknownComponent { source, component } => {
+                write!(f, "unknown component {} in {}", component, source)
+            }
+            PrefabError::BadComponent {
+                source,
+                component,
+                error,
+            } => write!(
+                f,
+                "invalid component {} in {}: {}",
+                component, source, error
+            ),
+            PrefabError::TextureNotLoaded { source, texture } => write!(
+                f,
+                "texture {} in {} isn't used by any prefab, so it wasn't loaded",
+                texture, source
+            ),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct SpriteConf {
+    pub texture: String,
+    pub tile_width: f32,
+    pub tile_height: f32,
+    pub row: f32,
+}
+
+#[derive(Deserialize, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct StrollingConf {
+    pub max_pause_seconds: f32,
+    #[serde(default)]
+    pub area: Option<StrollArea>,
+    #[serde(default)]
+    pub exclude: Vec<GridPosition>,
+}
+
+#[derive(Deserialize, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct PatrolConf {
+    #[serde(default)]
+    pub route: Vec<Waypoint>,
+    #[serde(default)]
+    pub schedule: Vec<ScheduleEntry>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct DialogChoiceTriggerConf {
+    pub dialog_id: u32,
+    pub actions: Vec<Action>,
+}
+
+#[derive(Debug)]
+pub enum PrefabComponent {
+    Sprite(SpriteConf),
+    Facing(Direction),
+    BlocksMovement(bool),
+    Strolling(StrollingConf),
+    Patrol(PatrolConf),
+    Elevation(usize),
+    TriggerOnEnter(Action),
+    TriggerOnExit(Action),
+    TriggerOnUse(Action),
+    TriggerOnDialogChoice(DialogChoiceTriggerConf),
+}
+
+// global resource with every prefab, along with the textures they use
+#[derive(Default, Clone)]
+pub struct Prefabs {
+    prefabs: HashMap<String, ComponentBundle>,
+    textures: HashMap<String, Texture2D>,
+}
+
+impl Prefabs {
+    pub async fn load(path: &str) -> Prefabs {
+        let prefabs_json_bytes = load_file(path)
+            .await
+            .unwrap_or_else(|_| panic!("failed to load {}", path));
+        let prefabs: HashMap<String, ComponentBundle> = serde_json::from_slice(&prefabs_json_bytes)
+            .unwrap_or_else(|err| panic!("failed to parse prefabs {}: {}", path, err));
+        let mut prefabs = Prefabs {
+            prefabs,
+            ..Default::default()
+        };
+
+        // check every prefab up front, so mistakes show up at startup instead of whenever a level happens to use one
+        let mut texture_paths = vec![];
+        for prefab in prefabs.prefabs.keys() {
+            let components = prefabs
+                .bundle(Some(prefab), ComponentBundle::new())
+                .and_then(|bundle| parse_bundle(prefab, &bundle))
+                .unwrap_or_else(|err| panic!("{}: {}", path, err));
+            for component in components {
+                if let PrefabComponent::Sprite(sprite) = component {
+                    texture_paths.push(sprite.texture);
+                }
+            }
+        }
+        for texture_path in texture_paths {
+            if !prefabs.textures.contains_key(&texture_path) {
+                let texture = load_texture(&texture_path).await;
+                prefabs.textures.insert(texture_path, texture);
+            }
+        }
+        prefabs
+    }
+
+    // resolves the prefab (if any) and merges the overrides on top of it
+    pub fn bundle(
+        &self,
+        prefab: Option<&str>,
+        overrides: ComponentBundle,
+    ) -> Result<ComponentBundle, PrefabError> {
+        let mut bundle = match prefab {
+            Some(prefab) => self.resolve(prefab, &mut vec![])?,
+            None => ComponentBundle::new(),
+        };
+        merge_bundles(&mut bundle, overrides);
+        Ok(bundle)
+    }
+
+    fn resolve(
+        &self,
+        prefab: &str,
+        seen: &mut Vec<String>,
+    ) -> Result<ComponentBundle, PrefabError> {
+        if seen.iter().any(|seen| seen == prefab) {
+            return Err(PrefabError::ExtendsCycle(prefab.to_owned()));
+        }
+        seen.push(prefab.to_owned());
+        let mut bundle = self
+            .prefabs
+            .get(prefab)
+            .ok_or_else(|| PrefabError::UnknownPrefab(prefab.to_owned()))?
+            .clone();
+        match bundle.remove("extends") {
+            Some(Value::String(base)) => {
+                let mut resolved = self.resolve(&base, seen)?;
+                merge_bundles(&mut resolved, bundle);
+                Ok(resolved)
+            }
+            Some(_) => Err(PrefabError::BadComponent {
+                source: prefab.to_owned(),
+                component: "extends".to_owned(),
+                error: "should be the name of another prefab".to_owned(),
+            }),
+            None => Ok(bundle),
+        }
+    }
+
+    // adds every component in the bundle to the entity being built
+    pub fn add_components<'a>(
+        &self,
+        source: &str,
+        mut builder: EntityBuilder<'a>,
+        bundle: &ComponentBundle,
+    ) -> Result<EntityBuilder<'a>, PrefabError> {
+        let components = parse_bundle(source, bundle)?;
+        // sprites start out showing the frame for the direction they face
+        let direction = components
+            .iter()
+            .find_map(|component| match component {
+                PrefabComponent::Facing(direction) => Some(*direction),
+                _ => None,
+            })
+            .unwrap_or(Direction::Down);
+        for component in components {
+            builder = match component {
+                PrefabComponent::Sprite(sprite) => {
+                    let texture = *self.textures.get(&sprite.texture).ok_or_else(|| {
+                        PrefabError::TextureNotLoaded {
+                            source: source.to_owned(),
+                            texture: sprite.texture.to_owned(),
+                        }
+                    })?;
+                    builder.with(SpriteDrawable {
+                        texture,
+                        tile_width: sprite.tile_width,
+                        tile_height: sprite.tile_height,
+                        row: sprite.row,
+                        current_frame: direction.get_character_facing_frame(),
+                    })
+                }
+                PrefabComponent::Facing(direction) => builder.with(FacingDirection { direction }),
+                PrefabComponent::BlocksMovement(true) => builder.with(BlocksMovement {}),
+                PrefabComponent::BlocksMovement(false) => builder,
+                PrefabComponent::Strolling(strolling) => builder.with(Strolling {
+                    max_pause_seconds: strolling.max_pause_seconds,
+                    area: strolling.area,
+                    exclude: strolling.exclude,
+                }),
+                PrefabComponent::Patrol(mut patrol) => {
+                    patrol
+                        .schedule
+                        .sort_by(|a, b| a.start_hour.partial_cmp(&b.start_hour).unwrap());
+                    builder.with(Patrol {
+                        route: patrol.route,
+                        schedule: patrol.schedule,
+                        next_waypoint: 0,
+                        remaining_wait_seconds: 0.,
+                        walking: false,
+                    })
+                }
+                PrefabComponent::Elevation(index) => builder.with(Elevation { index }),
+                PrefabComponent::TriggerOnEnter(action) => {
+                    builder.with(TriggerActionOnEnter { action })
+                }
+                PrefabComponent::TriggerOnExit(action) => {
+                    builder.with(TriggerActionOnExit { action })
+                }
+                PrefabComponent::TriggerOnUse(action) => {
+                    builder.with(TriggerActionOnUse { action })
+                }
+                PrefabComponent::TriggerOnDialogChoice(trigger) => {
+                    builder.with(TriggerActionOnDialogChoice {
+                        dialog_id: trigger.dialog_id,
+                        actions: trigger.actions,
+                    })
+                }
+            };
+        }
+        Ok(builder)
+    }
+}
+
+// objects are merged field by field, anything else in the overrides replaces what was there
+fn merge_bundles(bundle: &mut ComponentBundle, overrides: ComponentBundle) {
+    for (name, value) in overrides {
+        match (bundle.entry(name).or_insert(Value::Null), value) {
+            (Value::Object(existing), Value::Object(value)) => merge_bundles(existing, value),
+            (existing, value) => *existing = value,
+        }
+    }
+}
+
+fn parse_bundle(
+    source: &str,
+    bundle: &ComponentBundle,
+) -> Result<Vec<PrefabComponent>, PrefabError> {
+    bundle
+        .iter()
+        .map(|(name, value)| parse_component(source, name, value))
+        .collect()
+}
+
+fn parse_component(
+    source: &str,
+    name: &str,
+    value: &Value,
+) -> Result<PrefabComponent, PrefabError> {
+    fn parse<T: DeserializeOwned>(
+        source: &str,
+        name: &str,
+        value: &Value,
+    ) -> Result<T, PrefabError> {
+        serde_json::from_value(value.clone()).map_err(|err| PrefabError::BadComponent {
+            source: source.to_owned(),
+            component: name.to_owned(),
+            error: err.to_string(),
+        })
+    }
+    Ok(match name {
+        "sprite" => PrefabComponent::Sprite(parse(source, name, value)?),
+        "facing" => PrefabComponent::Facing(parse(source, name, value)?),
+        "blocks_movement" => PrefabComponent::BlocksMovement(parse(source, name, value)?),
+        "strolling" => PrefabComponent::Strolling(parse(source, name, value)?),
+        "patrol" => PrefabComponent::Patrol(parse(source, name, value)?),
+        "elevation" => PrefabComponent::Elevation(parse(source, name, value)?),
+        "trigger_on_enter" => PrefabComponent::TriggerOnEnter(parse(source, name, value)?),
+        "trigger_on_exit" => PrefabComponent::TriggerOnExit(parse(source, name, value)?),
+        "trigger_on_use" => PrefabComponent::TriggerOnUse(parse(source, name, value)?),
+        "trigger_on_dialog_choice" => {
+            PrefabComponent::TriggerOnDialogChoice(parse(source, name, value)?)
+        }
+        _ => {
+            return Err(PrefabError::UnknownComponent {
+                source: source.to_owned(),
+                component: name.to_owned(),
+            })
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use serde_json::json;
+
+    fn prefabs() -> Prefabs {
+        let prefabs = json!({
+            "character": {
+                "sprite": { "texture": "walk_cycle.png", "tile_width": 16, "tile_height": 24, "row": 0 },
+                "blocks_movement": true
+            },
+            "villager": {
+                "extends": "character",
+                "sprite": { "row": 4 },
+                "strolling": { "max_pause_seconds": 3 }
+            },
+            "loop_a": { "extends": "loop_b" },
+            "loop_b": { "extends": "loop_a" }
+        });
+        Prefabs {
+            prefabs: serde_json::from_value(prefabs).unwrap(),
+            ..Default::default()
+        }
+    }
+
+    fn bundle(value: Value) -> ComponentBundle {
+        serde_json::from_value(value).unwrap()
+    }
+
+    #[test]
+    fn test_extends_and_overrides() {
+        let prefabs = prefabs();
+        let villager = prefabs
+            .bundle(
+                Some("villager"),
+                bundle(json!({ "facing": "Left", "strolling": { "max_pause_seconds": 5 } })),
+            )
+            .unwrap();
+        assert_eq!(
+            villager,
+            bundle(json!({
+                "sprite": { "texture": "walk_cycle.png", "tile_width": 16, "tile_height": 24, "row": 4 },
+                "blocks_movement": true,
+                "strolling": { "max_pause_seconds": 5 },
+                "facing": "Left"
+            }))
+        );
+        let components = parse_bundle("villager", &villager).unwrap();
+        assert_eq!(components.len(), 4);
+        assert!(components.iter().any(|component| match component {
+            PrefabComponent::Sprite(sprite) => sprite.row == 4. && sprite.tile_height == 24.,
+            _ => false,
+        }));
+        assert!(components
+            .iter()
+            .any(|component| matches!(component, PrefabComponent::Facing(Direction::Left))));
+    }
+
+    #[test]
+    fn test_strolling_area() {
+        let strolling = bundle(json!({
+            "strolling": {
+                "max_pause_seconds": 2,
+                "area": { "x": 6, "y": 8, "radius": 3 },
+                "exclude": [{ "x": 6, "y": 9 }]
+            }
+        }));
+        match &parse_bundle("villager", &strolling).unwrap()[0] {
+            PrefabComponent::Strolling(strolling) => {
+                assert_eq!(
+                    strolling.area,
+                    Some(StrollArea::Circle {
+                        x: 6.,
+                        y: 8.,
+                        radius: 3.
+                    })
+                );
+                assert_eq!(strolling.exclude, vec![GridPosition { x: 6., y: 9. }]);
+            }
+            component => panic!("unexpected component {:?}", component),
+        }
+        let rect = bundle(json!({
+            "strolling": { "max_pause_seconds": 2, "area": { "x": 1, "y": 3, "width": 4, "height": 5 } }
+        }));
+        assert!(matches!(
+            &parse_bundle("villager", &rect).unwrap()[0],
+            PrefabComponent::Strolling(StrollingConf {
+                area: Some(StrollArea::Rect { .. }),
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_patrol() {
+        let guard = bundle(json!({
+            "patrol": {
+                "schedule": [
+                    { "start_hour": 20, "route": [{ "x": 9, "y": 9 }] },
+                    { "start_hour": 8, "route": [{ "x": 1, "y": 2, "wait_seconds": 3, "facing": "Up" }, { "x": 5, "y": 2 }] }
+                ]
+            }
+        }));
+        match &parse_bundle("guard", &guard).unwrap()[0] {
+            PrefabComponent::Patrol(patrol) => {
+                assert!(patrol.route.is_empty());
+                assert_eq!(patrol.schedule[1].route[0].facing, Some(Direction::Up));
+                assert_eq!(patrol.schedule[1].route[1].wait_seconds, 0.);
+            }
+            component => panic!("unexpected component {:?}", component),
+        }
+        let typo = bundle(json!({ "patrol": { "route": [{ "x": 1, "y": 2, "wait": 3 }] } }));
+        assert!(matches!(
+            parse_bundle("guard", &typo).unwrap_err(),
+            PrefabError::BadComponent { .. }
+        ));
+    }
+
+    #[test]
+    fn test_no_prefab() {
+        let overrides = bundle(json!({ "trigger_on_use": { "ShowSimpleDialog": "urn.snakes" } }));
+        let urn = prefabs().bundle(None, overrides.clone()).unwrap();
+        assert_eq!(urn, overrides);
+        assert!(matches!(
+            parse_bundle("urn", &urn).unwrap()[0],
+            PrefabComponent::TriggerOnUse(Action::ShowSimpleDialog(_))
+        ));
+    }
+
+    #[test]
+    fn test_errors() {
+        let prefabs = prefabs();
+        assert_eq!(
+            prefabs.bundle(Some("vilager"), ComponentBundle::new()),
+            Err(PrefabError::UnknownPrefab("vilager".to_owned()))
+        );
+        assert_eq!(
+            prefabs.bundle(Some("loop_a"), ComponentBundle::new()),
+            Err(PrefabError::ExtendsCycle("loop_a".to_owned()))
+        );
+        assert_eq!(
+            parse_bundle("villager", &bundle(json!({ "sprit": {} }))).unwrap_err(),
+            PrefabError::UnknownComponent {
+                source: "villager".to_owned(),
+                component: "sprit".to_owned(),
+            }
+        );
+        // bad fields name the field in the error
+        let bad_field = bundle(json!({ "strolling": { "max_pause": 3 } }));
+        match parse_bundle("villager", &bad_field).unwrap_err() {
+            PrefabError::BadComponent {
+                component, error, ..
+            } => {
+                assert_eq!(component, "strolling");
+                assert!(error.contains("max_pause"), "{}", error);
+            }
+            err => panic!("unexpected error {}", err),
+        }
+        let missing_field = bundle(json!({ "sprite": { "row": 4 } }));
+        assert!(matches!(
+            parse_bundle("villager", &missing_field).unwrap_err(),
+            PrefabError::BadComponent { .. }
+        ));
+    }
+}
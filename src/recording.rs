@@ -0,0 +1,274 @@
+use crate::bindings::BindingsFile;
+use crate::bindings::KeyBindings;
+use crate::constants::RECORDING_VERSION;
+use crate::gamepad::GamepadButton;
+use crate::gamepad::GamepadProfile;
+use crate::platform::key_from_name;
+use crate::platform::Input;
+use serde::Deserialize;
+use serde::Serialize;
+use std::fs::File;
+use std::io::BufWriter;
+use std::io::Write;
+
+// An input recording has everything needed to play a session back exactly: the level it started on, the Rng seed,
+// the key bindings and gamepad profile (so changing controls later doesn't change what a recording does), and then for every frame the frame time
+// and which keys and gamepad buttons were down, and where the mouse clicked. Since every system reads time, input and random
+// numbers from resources (see platform.rs), feeding those back in gives the same positions and events, frame for frame.
+//
+// The file is JSON lines: a RecordingHeader, then one RecordedFrame per line. That way it's written as the game runs,
+// and a session that ended with a crash can still be replayed up to the crash.
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RecordingHeader {
+    pub version: u32,
+    pub level_id: String,
+    pub seed: u64,
+    pub key_bindings: BindingsFile,
+    pub gamepad: GamepadProfile,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RecordedFrame {
+    pub delta_seconds: f32,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub keys_down: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub keys_pressed: Vec<String>,
+    // the left stick is already a button here, since the dead zone was applied when it was read
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub buttons_down: Vec<GamepadButton>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub buttons_pressed: Vec<GamepadButton>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub click: Option<(f32, f32)>, // screen position, which is enough since the camera is in the same place on replay
+}
+
+impl RecordedFrame {
+    fn new(delta_seconds: f32, input: &Input) -> RecordedFrame {
+        let (keys_down, keys_pressed) = input.key_names();
+        let (buttons_down, buttons_pressed) = input.buttons();
+        RecordedFrame {
+            delta_seconds,
+            keys_down: keys_down.into_iter().map(str::to_owned).collect(),
+            keys_pressed: keys_pressed.into_iter().map(str::to_owned).collect(),
+            buttons_down,
+            buttons_pressed,
+            click: input.click(),
+        }
+    }
+
+    fn input(&self) -> Result<Input, String> {
+        let mut input = Input {
+            ..Default::default()
+        };
+        for name in self.keys_down.iter() {
+            input = input.hold(key_from_name(name).ok_or_else(|| format!("unknown key {}", name))?);
+        }
+        for name in self.keys_pressed.iter() {
+            input =
+                input.press(key_from_name(name).ok_or_else(|| format!("unknown key {}", name))?);
+        }
+        for button in self.buttons_down.iter() {
+            input = input.hold_button(*button);
+        }
+        for button in self.buttons_pressed.iter() {
+            input = input.press_button(*button);
+        }
+        if let Some((screen_x, screen_y)) = self.click {
+            input = input.click_at(screen_x, screen_y);
+        }
+        Ok(input)
+    }
+}
+
+// Writes each frame to the recording as it happens
+pub struct Recorder<W: Write> {
+    writer: W,
+}
+
+impl Recorder<BufWriter<File>> {
+    pub fn create(path: &str, header: &RecordingHeader) -> Result<Self, String> {
+        let file = File::create(path).map_err(|err| err.to_string())?;
+        Recorder::new(BufWriter::new(file), header)
+    }
+}
+
+impl<W: Write> Recorder<W> {
+    pub fn new(writer: W, header: &RecordingHeader) -> Result<Self, String> {
+        let mut recorder = Recorder { writer };
+        recorder.write_line(header)?;
+        Ok(recorder)
+    }
+
+    pub fn record(&mut self, delta_seconds: f32, input: &Input) -> Result<(), String> {
+        self.write_line(&RecordedFrame::new(delta_seconds, input))
+    }
+
+    fn write_line<T: Serialize>(&mut self, line: &T) -> Result<(), String> {
+        serde_json::to_writer(&mut self.writer, line).map_err(|err| err.to_string())?;
+        // flushed every frame, so nothing is lost if the game crashes
+        writeln!(self.writer)
+            .and_then(|_| self.writer.flush())
+            .map_err(|err| err.to_string())
+    }
+
+    #[cfg(test)]
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+// global resource that's set while the frames come from a replay rather than live input.
+// a replay leaves the player's own files alone, so nothing is saved, loaded or written to the controls config until it's over.
+#[derive(Default)]
+pub struct ReplayState {
+    pub replaying: bool,
+}
+
+// Hands out the frames of a recording, one per game frame
+pub struct Replay {
+    pub header: RecordingHeader,
+    frames: Vec<RecordedFrame>,
+    next_frame: usize,
+}
+
+impl Replay {
+    pub fn load(path: &str) -> Result<Replay, String> {
+        let text = std::fs::read_to_string(path).map_err(|err| err.to_string())?;
+        Replay::parse(&text)
+    }
+
+    pub fn parse(text: &str) -> Result<Replay, String> {
+        let mut lines = text
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty());
+        let header: RecordingHeader = match lines.next() {
+            Some((_, line)) => {
+                serde_json::from_str(line).map_err(|err| format!("bad header: {}", err))?
+            }
+            None => return Err("recording is empty".to_owned()),
+        };
+        if header.version != RECORDING_VERSION {
+            return Err(format!(
+                "recording is version {}, but this game only replays version {}",
+                header.version, RECORDING_VERSION
+            ));
+        }
+        KeyBindings::from_file(&header.key_bindings, header.gamepad.clone())
+            .map_err(|err| format!("bad header: {}", err))?;
+        let mut frames = vec![];
+        for (index, line) in lines {
+            let frame: RecordedFrame = serde_json::from_str(line)
+                .map_err(|err| format!("bad frame on line {}: {}", index + 1, err))?;
+            // check the keys now, rather than part way through the replay
+            frame
+                .input()
+                .map_err(|err| format!("bad frame on line {}: {}", index + 1, err))?;
+            frames.push(frame);
+        }
+        Ok(Replay {
+            header,
+            frames,
+            next_frame: 0,
+        })
+    }
+
+    // the frame time and input for the next frame, or None once the whole recording has been played
+    pub fn next_frame(&mut self) -> Option<(f32, Input)> {
+        let frame = self.frames.get(self.next_frame)?;
+        self.next_frame += 1;
+        let input = frame.input().expect("keys were checked when parsing");
+        Some((frame.delta_seconds, input))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::bindings::InputAction;
+    use macroquad::prelude::KeyCode;
+
+    fn header() -> RecordingHeader {
+        RecordingHeader {
+            version: RECORDING_VERSION,
+            level_id: "small_room".to_owned(),
+            seed: 1234,
+            key_bindings: serde_json::from_str(
+                r#"{ "keys": { "MoveRight": ["Right"], "Use": ["Space"] } }"#,
+            )
+            .unwrap(),
+            gamepad: GamepadProfile {
+                ..Default::default()
+            },
+        }
+    }
+
+    #[test]
+    fn test_record_and_replay() {
+        let inputs = vec![
+            (0.016, Input::default()),
+            (0.017, Input::default().press(KeyCode::Right)),
+            (0.033, Input::default().hold(KeyCode::Right)),
+            (
+                0.0166667,
+                Input::default().hold(KeyCode::Right).press(KeyCode::Space),
+            ),
+            (
+                0.02,
+                Input::default()
+                    .hold_button(GamepadButton::StickDown)
+                    .press_button(GamepadButton::South),
+            ),
+            (0.02, Input::default().click_at(120.5, 300.)),
+        ];
+        let mut recorder = Recorder::new(vec![], &header()).unwrap();
+        for (delta_seconds, input) in inputs.iter() {
+            recorder.record(*delta_seconds, input).unwrap();
+        }
+        let text = String::from_utf8(recorder.into_inner()).unwrap();
+        assert_eq!(text.lines().count(), 7);
+        assert_eq!(
+            text.lines().nth(2).unwrap(),
+            r#"{"delta_seconds":0.017,"keys_down":["Right"],"keys_pressed":["Right"]}"#
+        );
+
+        let mut replay = Replay::parse(&text).unwrap();
+        assert_eq!(replay.header, header());
+        for (delta_seconds, input) in inputs.into_iter() {
+            assert_eq!(replay.next_frame(), Some((delta_seconds, input)));
+        }
+        assert_eq!(replay.next_frame(), None);
+    }
+
+    #[test]
+    fn test_bad_recordings() {
+        assert!(Replay::parse("").is_err());
+        let newer = format!(
+            "{{\"version\":{},\"level_id\":\"small_room\",\"seed\":1,\"key_bindings\":{{}},\"gamepad\":{}}}",
+            RECORDING_VERSION + 1,
+            serde_json::to_string(&header().gamepad).unwrap()
+        );
+        assert!(Replay::parse(&newer).is_err());
+        let mut unknown_key = header();
+        unknown_key
+            .key_bindings
+            .keys
+            .insert(InputAction::Use, vec!["Hyper".to_owned()]);
+        assert_eq!(
+            Replay::parse(&serde_json::to_string(&unknown_key).unwrap()).err(),
+            Some("bad header: unknown key Hyper for Use".to_owned())
+        );
+        let header = serde_json::to_string(&header()).unwrap();
+        assert_eq!(
+            Replay::parse(&format!(
+                "{}\n{{\"delta_seconds\":0.1,\"keys_down\":[\"Escape\"]}}",
+                header
+            ))
+            .err(),
+            Some("bad frame on line 2: unknown key Escape".to_owned())
+        );
+    }
+}
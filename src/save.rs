@@ -0,0 +1,492 @@
+use crate::actions::ActionQueue;
+use crate::actions::FiredTriggers;
+use crate::clock::GameClock;
+use crate::components::AwaitingInputState;
+use crate::components::Direction;
+use crate::components::Elevation;
+use crate::components::EntityMovingState;
+use crate::components::FacingDirection;
+use crate::components::GridPosition;
+use crate::components::PersistentId;
+use crate::components::PlayerEntity;
+use crate::components::SpriteDrawable;
+use crate::constants::SAVE_DIRECTORY;
+use crate::constants::SAVE_SLOTS;
+use crate::constants::SAVE_VERSION;
+use crate::dialogs::DialogTrees;
+use crate::events::EventQueue;
+use crate::level::change_level;
+use crate::level::LevelChange;
+use crate::level::PendingLevelChange;
+use crate::map::GameMap;
+use crate::recording::ReplayState;
+use crate::ui::DialogBoxState;
+use crate::ui::UiState;
+use macroquad::prelude::load_texture;
+use serde::Deserialize;
+use serde::Serialize;
+use serde_json::Value;
+use specs::Join;
+use specs::World;
+use specs::WorldExt;
+use std::collections::HashMap;
+use std::fmt;
+
+// A save file holds everything about the game that can't be rebuilt from the level files:
+// - the current level
+// - the position, facing and elevation of every entity with a PersistentId (the player, and everything spawned from the map)
+// - which triggers have already fired
+// - the open dialog box, if any
+// - the time of day
+// Loading a save reloads the level from scratch and then applies all of that on top.
+//
+// Save files are versioned. Bump SAVE_VERSION whenever the format changes, and upgrade older saves in parse_save.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SaveFile {
+    pub version: u32,
+    pub level_id: String,
+    pub entities: Vec<SavedEntity>,
+    pub fired_triggers: Vec<String>,
+    pub dialog_box: Option<DialogBoxState>,
+    #[serde(default)]
+    pub clock_minutes: Option<f32>, // missing from version 1 saves, which leave the clock as it is
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct SavedEntity {
+    pub id: String, // PersistentId
+    pub position: GridPosition,
+    #[serde(default)]
+    pub facing: Option<Direction>,
+    #[serde(default)]
+    pub elevation: usize,
+}
+
+#[derive(Debug)]
+pub enum SaveError {
+    Io(String),
+    Parse(String),
+    UnsupportedVersion(u32),
+    #[cfg(target_arch = "wasm32")]
+    UnsupportedPlatform,
+}
+
+impl fmt::Display for SaveError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SaveError::Io(err) => write!(f, "{}", err),
+            SaveError::Parse(err) => write!(f, "save file is corrupt: {}", err),
+            SaveError::UnsupportedVersion(version) => write!(
+                f,
+                "save file version {} isn't supported, this game reads up to version {}",
+                version, SAVE_VERSION
+            ),
+            #[cfg(target_arch = "wasm32")]
+            SaveError::UnsupportedPlatform => write!(f, "saving isn't supported on this platform"),
+        }
+    }
+}
+
+// global resource with the selected save slot, and any save/load the player asked for this frame.
+// like level changes, saving and loading is done by the main loop in between frames, since loading needs the whole World.
+#[derive(Default)]
+pub struct SaveSlots {
+    pub current_slot: usize, // from 0 to SAVE_SLOTS - 1
+    pub request: Option<SaveRequest>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum SaveRequest {
+    Save,
+    Load,
+}
+
+// carries out any save or load the player asked for
+pub async fn handle_save_request(world: &mut World) {
+    let (request, slot) = {
+        let mut save_slots = world.write_resource::<SaveSlots>();
+        (save_slots.request.take(), save_slots.current_slot)
+    };
+    // the save slot files aren't part of a recording, so a replay can't use them
+    if world.read_resource::<ReplayState>().replaying {
+        match request {
+            Some(SaveRequest::Save) => println!("Not saving to slot {} during a replay", slot + 1),
+            Some(SaveRequest::Load) => println!(
+                "WARNING: not loading slot {} during a replay, so the replay won't match the recording from here",
+                slot + 1
+            ),
+            None => (),
+        }
+        return;
+    }
+    match request {
+        Some(SaveRequest::Save) => match write_slot(slot, &capture(world)) {
+            Ok(()) => println!("Saved game to slot {}", slot + 1),
+            Err(err) => println!("WARNING: failed to save to slot {}: {}", slot + 1, err),
+        },
+        Some(SaveRequest::Load) => match read_slot(slot) {
+            Ok(save) => {
+                restore(world, save).await;
+                println!("Loaded game from slot {}", slot + 1);
+            }
+            Err(err) => println!("WARNING: failed to load slot {}: {}", slot + 1, err),
+        },
+        None => (),
+    }
+}
+
+pub fn capture(world: &World) -> SaveFile {
+    let mut fired_triggers: Vec<String> = world
+        .read_resource::<FiredTriggers>()
+        .ids
+        .iter()
+        .cloned()
+        .collect();
+    fired_triggers.sort(); // so saving the same game twice gives the same file
+    SaveFile {
+        version: SAVE_VERSION,
+        level_id: world.read_resource::<GameMap>().level_id.to_owned(),
+        entities: capture_entities(world),
+        fired_triggers,
+        dialog_box: world.read_resource::<UiState>().dialog_box_state(),
+        clock_minutes: Some(world.read_resource::<GameClock>().minutes),
+    }
+}
+
+fn capture_entities(world: &World) -> Vec<SavedEntity> {
+    let persistent_ids = world.read_storage::<PersistentId>();
+    let positions = world.read_storage::<GridPosition>();
+    let facing_directions = world.read_storage::<FacingDirection>();
+    let elevations = world.read_storage::<Elevation>();
+    let moving_states = world.read_storage::<EntityMovingState>();
+    let mut saved_entities: Vec<SavedEntity> = (
+        &persistent_ids,
+        &positions,
+        facing_directions.maybe(),
+        elevations.maybe(),
+        moving_states.maybe(),
+    )
+        .join()
+        .map(
+            |(persistent_id, position, facing_direction, elevation, moving_state)| {
+                // entities in the middle of a step are saved on the tile they're stepping onto.
+                // the deltas count down the distance left to move.
+                let (delta_x, delta_y) =
+                    moving_state.map_or((0., 0.), |moving| (moving.delta_x, moving.delta_y));
+                SavedEntity {
+                    id: persistent_id.id.to_owned(),
+                    position: GridPosition {
+                        x: (position.x + delta_x).round(),
+                        y: (position.y + delta_y).round(),
+                    },
+                    facing: facing_direction.map(|facing| facing.direction),
+                    elevation: Elevation::index_of(elevation),
+                }
+            },
+        )
+        .collect();
+    saved_entities.sort_by(|a, b| a.id.cmp(&b.id));
+    saved_entities
+}
+
+pub async fn restore(world: &mut World, save: SaveFile) {
+    // reload the level, so anything not in the save file is back to how the level starts
+    let player_entity = world.read_resource::<PlayerEntity>().entity;
+    let player_id = world
+        .read_storage::<PersistentId>()
+        .get(player_entity)
+        .map(|persistent_id| persistent_id.id.to_owned());
+    let saved_player = save
+        .entities
+        .iter()
+        .find(|saved| Some(&saved.id) == player_id.as_ref());
+    let (position, facing) = match saved_player {
+        Some(saved) => (saved.position, saved.facing.unwrap_or(Direction::Down)),
+        None => {
+            println!("WARNING: save file has no player, leaving them where they are");
+            let position = *world
+                .read_storage::<GridPosition>()
+                .get(player_entity)
+                .expect("player has no position");
+            (position, Direction::Down)
+        }
+    };
+    change_level(
+        world,
+        LevelChange {
+            level_id: save.level_id.to_owned(),
+            position,
+            facing,
+        },
+    )
+    .await;
+    apply_entities(world, &save.entities);
+
+    // the player may have been mid-step, so put them back to waiting for input
+    world
+        .write_storage::<EntityMovingState>()
+        .remove(player_entity);
+    world
+        .write_storage::<AwaitingInputState>()
+        .insert(player_entity, AwaitingInputState {})
+        .expect("failed to insert player AwaitingInputState");
+    // anything that was about to happen belongs to the game we just left
+    *world.write_resource::<EventQueue>() = EventQueue {
+        ..Default::default()
+    };
+    world.write_resource::<ActionQueue>().actions.clear();
+    world.write_resource::<PendingLevelChange>().level_change = None;
+
+    world.write_resource::<FiredTriggers>().ids = save.fired_triggers.into_iter().collect();
+    if let Some(minutes) = save.clock_minutes {
+        world.write_resource::<GameClock>().minutes = minutes;
+    }
+    // textures aren't saved, so load the dialog box's portrait again from its path
+    let mut dialog_box = save.dialog_box;
+    if let Some(conf) = dialog_box.as_mut().map(|state| &mut state.conf) {
+        if let Some(path) = conf.portrait_path.to_owned() {
+            let cached = world
+                .read_resource::<DialogTrees>()
+                .portraits
+                .get(&path)
+                .copied();
+            conf.portrait = Some(match cached {
+                Some(texture) => texture,
+                None => load_texture(&path).await,
+            });
+        }
+    }
+    let dialog_trees = world.read_resource::<DialogTrees>();
+    world
+        .write_resource::<UiState>()
+        .restore_dialog_box(dialog_box, &dialog_trees);
+}
+
+fn apply_entities(world: &mut World, saved_entities: &[SavedEntity]) {
+    let entities = world.entities();
+    let persistent_ids = world.read_storage::<PersistentId>();
+    let mut positions = world.write_storage::<GridPosition>();
+    let mut facing_directions = world.write_storage::<FacingDirection>();
+    let mut elevations = world.write_storage::<Elevation>();
+    let mut drawables = world.write_storage::<SpriteDrawable>();
+    let entities_by_id: HashMap<&str, _> = (&entities, &persistent_ids)
+        .join()
+        .map(|(entity, persistent_id)| (persistent_id.id.as_str(), entity))
+        .collect();
+    for saved in saved_entities {
+        let entity = match entities_by_id.get(saved.id.as_str()) {
+            Some(entity) => *entity,
+            None => {
+                // e.g. the level was changed since the game was saved
+                println!("WARNING: save file has unknown entity {}", saved.id);
+                continue;
+            }
+        };
+        positions
+            .insert(entity, saved.position)
+            .expect("failed to restore position");
+        elevations
+            .insert(
+                entity,
+                Elevation {
+                    index: saved.elevation,
+                },
+            )
+            .expect("failed to restore elevation");
+        if let Some(direction) = saved.facing {
+            facing_directions
+                .insert(entity, FacingDirection { direction })
+                .expect("failed to restore facing direction");
+            if let Some(drawable) = drawables.get_mut(entity) {
+                drawable.current_frame = direction.get_character_facing_frame();
+            }
+        }
+    }
+}
+
+fn slot_path(slot: usize) -> String {
+    format!("{}/slot{}.json", SAVE_DIRECTORY, slot + 1)
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn write_slot(slot: usize, save: &SaveFile) -> Result<(), SaveError> {
+    assert!(slot < SAVE_SLOTS, "invalid save slot {}", slot);
+    let json = serde_json::to_string_pretty(save).map_err(|err| SaveError::Io(err.to_string()))?;
+    std::fs::create_dir_all(SAVE_DIRECTORY).map_err(|err| SaveError::Io(err.to_string()))?;
+    std::fs::write(slot_path(slot), json).map_err(|err| SaveError::Io(err.to_string()))
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn read_slot(slot: usize) -> Result<SaveFile, SaveError> {
+    assert!(slot < SAVE_SLOTS, "invalid save slot {}", slot);
+    let json =
+        std::fs::read_to_string(slot_path(slot)).map_err(|err| SaveError::Io(err.to_string()))?;
+    parse_save(&json)
+}
+
+// TODO: the browser build could keep saves in local storage
+#[cfg(target_arch = "wasm32")]
+fn write_slot(_slot: usize, _save: &SaveFile) -> Result<(), SaveError> {
+    Err(SaveError::UnsupportedPlatform)
+}
+
+#[cfg(target_arch = "wasm32")]
+fn read_slot(_slot: usize) -> Result<SaveFile, SaveError> {
+    Err(SaveError::UnsupportedPlatform)
+}
+
+// checks the version before anything else, so saves from a newer version of the game give a clear error
+fn parse_save(json: &str) -> Result<SaveFile, SaveError> {
+    let save: Value =
+        serde_json::from_str(json).map_err(|err| SaveError::Parse(err.to_string()))?;
+    let version = save["version"]
+        .as_u64()
+        .ok_or_else(|| SaveError::Parse("missing version".to_owned()))? as u32;
+    if version == 0 || version > SAVE_VERSION {
+        return Err(SaveError::UnsupportedVersion(version));
+    }
+    serde_json::from_value(save).map_err(|err| SaveError::Parse(err.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::ui::DialogBoxConf;
+    use specs::Builder;
+
+    fn world() -> World {
+        let mut world = World::new();
+        world.register::<PersistentId>();
+        world.register::<GridPosition>();
+        world.register::<FacingDirection>();
+        world.register::<Elevation>();
+        world.register::<EntityMovingState>();
+        world.register::<SpriteDrawable>();
+        world
+    }
+
+    #[test]
+    fn test_capture_and_apply_entities() {
+        let mut world = world();
+        let player = world
+            .create_entity()
+            .with(PersistentId {
+                id: "player".to_owned(),
+            })
+            .with(GridPosition { x: 6., y: 7.25 })
+            .with(FacingDirection {
+                direction: Direction::Up,
+            })
+            .with(Elevation { index: 1 })
+            // a quarter of the way through stepping up from 6,8 to 6,7
+            .with(EntityMovingState {
+                delta_x: 0.,
+                delta_y: -0.25,
+                direction: Direction::Up,
+            })
+            .build();
+        world
+            .create_entity()
+            .with(PersistentId {
+                id: "small_room/6".to_owned(),
+            })
+            .with(GridPosition { x: 10., y: 3. })
+            .build();
+        // entities without a PersistentId aren't saved
+        world
+            .create_entity()
+            .with(GridPosition { x: 1., y: 1. })
+            .build();
+
+        let saved = capture_entities(&world);
+        assert_eq!(
+            saved,
+            vec![
+                SavedEntity {
+                    id: "player".to_owned(),
+                    position: GridPosition { x: 6., y: 7. },
+                    facing: Some(Direction::Up),
+                    elevation: 1,
+                },
+                SavedEntity {
+                    id: "small_room/6".to_owned(),
+                    position: GridPosition { x: 10., y: 3. },
+                    facing: None,
+                    elevation: 0,
+                },
+            ]
+        );
+
+        // a fresh copy of the world gets everything back, whatever order the entities were created in
+        let mut restored = world();
+        restored
+            .create_entity()
+            .with(PersistentId {
+                id: "small_room/6".to_owned(),
+            })
+            .with(GridPosition { x: 0., y: 0. })
+            .build();
+        let restored_player = restored
+            .create_entity()
+            .with(PersistentId {
+                id: "player".to_owned(),
+            })
+            .with(GridPosition { x: 2., y: 4. })
+            .build();
+        assert_ne!(player.id(), restored_player.id());
+        apply_entities(&mut restored, &saved);
+        assert_eq!(capture_entities(&restored), saved);
+    }
+
+    #[test]
+    fn test_parse_save() {
+        let save = SaveFile {
+            version: SAVE_VERSION,
+            level_id: "small_room".to_owned(),
+            entities: vec![SavedEntity {
+                id: "player".to_owned(),
+                position: GridPosition { x: 2., y: 4. },
+                facing: Some(Direction::Left),
+                elevation: 0,
+            }],
+            fired_triggers: vec!["small_room/3".to_owned()],
+            dialog_box: Some(DialogBoxState {
+                conf: DialogBoxConf {
+                    message: "villager.hello".to_owned(),
+                    portrait_path: Some("assets/texture/villager.png".to_owned()),
+                    ..Default::default()
+                },
+                conversation_node: None,
+                current_page: 1,
+                selected_choice: 0,
+            }),
+            clock_minutes: Some(754.5),
+        };
+        let parsed = parse_save(&serde_json::to_string(&save).unwrap()).unwrap();
+        assert_eq!(parsed.level_id, "small_room");
+        assert_eq!(parsed.entities, save.entities);
+        assert_eq!(parsed.fired_triggers, save.fired_triggers);
+        assert_eq!(parsed.clock_minutes, Some(754.5));
+        let dialog_box = parsed.dialog_box.unwrap();
+        assert_eq!(
+            dialog_box.conf.portrait_path.as_deref(),
+            Some("assets/texture/villager.png")
+        );
+        assert_eq!(dialog_box.current_page, 1);
+        // version 1 saves don't have the time of day
+        let version_1 = r#"{ "version": 1, "level_id": "small_room", "entities": [], "fired_triggers": [], "dialog_box": null }"#;
+        assert_eq!(parse_save(version_1).unwrap().clock_minutes, None);
+
+        let from_the_future = format!("{{ \"version\": {} }}", SAVE_VERSION + 1);
+        assert!(matches!(
+            parse_save(&from_the_future),
+            Err(SaveError::UnsupportedVersion(_))
+        ));
+        assert!(matches!(parse_save("{}"), Err(SaveError::Parse(_))));
+        assert!(matches!(
+            parse_save("{ \"version\": 1 }"),
+            Err(SaveError::Parse(_))
+        ));
+    }
+}
@@ -0,0 +1,529 @@
+use crate::bindings::KeyBindings;
+use crate::components::AwaitingInputState;
+use crate::components::GridPosition;
+use crate::components::PlayerEntity;
+use crate::constants::DIALOG_TREE_FILES;
+use crate::constants::LANGUAGES;
+use crate::dialogs::DialogTrees;
+use crate::fonts;
+use crate::game::create_world;
+use crate::game::finish_frame;
+use crate::gamepad::GamepadProfiles;
+use crate::level::spawn_level_entities;
+use crate::localization::Localization;
+use crate::platform::set_headless;
+use crate::platform::Input;
+use crate::platform::Rng;
+use crate::platform::Time;
+use crate::systems::simulation_dispatcher_builder;
+use specs::Dispatcher;
+use specs::World;
+use specs::WorldExt;
+use std::future::Future;
+use std::task::Context;
+use std::task::Poll;
+use std::task::RawWaker;
+use std::task::RawWakerVTable;
+use std::task::Waker;
+
+// length of every simulated frame
+const FRAME_SECONDS: f32 = 1. / 60.;
+
+// Runs the game without a window: no rendering systems, a fixed frame time, a seeded Rng, and input supplied by the caller one frame at a time.
+// This is what integration tests use, e.g. walk the player somewhere, press use, and check what happened.
+pub struct Simulation {
+    pub world: World,
+    dispatcher: Dispatcher<'static, 'static>,
+}
+
+impl Simulation {
+    pub fn new(level_id: &str, seed: u64) -> Simulation {
+        set_headless();
+        block_on(async {
+            let localization = Localization::load(LANGUAGES).await;
+            let dialog_trees = DialogTrees::load(DIALOG_TREE_FILES).await;
+            let mut font_characters = fonts::base_characters();
+            font_characters.extend(localization.characters());
+            let font_atlas = fonts::load_font(&font_characters).build_atlas();
+            let gamepad_profiles = GamepadProfiles::load().await;
+            let mut world = create_world(
+                level_id,
+                font_atlas,
+                localization,
+                dialog_trees,
+                Rng::new(seed),
+                KeyBindings::load_defaults(&gamepad_profiles).await,
+                gamepad_profiles,
+            )
+            .await;
+            let mut dispatcher = simulation_dispatcher_builder().build();
+            dispatcher.setup(&mut world);
+            spawn_level_entities(&mut world);
+            Simulation { world, dispatcher }
+        })
+    }
+
+    // runs a single frame with the given input
+    pub fn step(&mut self, input: Input) {
+        self.step_with_delta(FRAME_SECONDS, input);
+    }
+
+    // runs a single frame of the given length, e.g. one from an input recording
+    pub fn step_with_delta(&mut self, delta_seconds: f32, input: Input) {
+        self.world.write_resource::<Time>().advance(delta_seconds);
+        *self.world.write_resource::<Input>() = input;
+        self.dispatcher.dispatch(&self.world);
+        self.world.maintain();
+        block_on(finish_frame(&mut self.world));
+    }
+
+    // runs frames with no input until the player can move again, e.g. after a step or a level change
+    pub fn wait_for_player(&mut self) {
+        for _ in 0..600 {
+            if self.is_awaiting_input() {
+                return;
+            }
+            self.step(Input::default());
+        }
+        panic!("player still isn't awaiting input after 600 frames");
+    }
+
+    pub fn is_awaiting_input(&self) -> bool {
+        let player_entity = self.world.read_resource::<PlayerEntity>().entity;
+        self.world
+            .read_storage::<AwaitingInputState>()
+            .contains(player_entity)
+    }
+
+    pub fn player_position(&self) -> GridPosition {
+        let player_entity = self.world.read_resource::<PlayerEntity>().entity;
+        *self
+            .world
+            .read_storage::<GridPosition>()
+            .get(player_entity)
+            .expect("player has no position")
+    }
+}
+
+// Runs a future to completion on the current thread. Loading is only async because macroquad's file loading is,
+// and outside the browser that completes straight away, so there's no need for a real executor here.
+fn block_on<F: Future>(future: F) -> F::Output {
+    fn noop_raw_waker() -> RawWaker {
+        fn clone(_: *const ()) -> RawWaker {
+            noop_raw_waker()
+        }
+        fn noop(_: *const ()) {}
+        static VTABLE: RawWakerVTable = RawWakerVTable::new(clone, noop, noop, noop);
+        RawWaker::new(std::ptr::null(), &VTABLE)
+    }
+    let waker = unsafe { Waker::from_raw(noop_raw_waker()) };
+    let mut context = Context::from_waker(&waker);
+    let mut future = Box::pin(future);
+    loop {
+        if let Poll::Ready(output) = future.as_mut().poll(&mut context) {
+            return output;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::actions::Action;
+    use crate::actions::ActionQueue;
+    use crate::camera::Camera;
+    use crate::components::Direction;
+    use crate::components::FacingDirection;
+    use crate::components::Patrol;
+    use crate::components::PersistentId;
+    use crate::components::WalkingPath;
+    use crate::components::Waypoint;
+    use crate::constants::RECORDING_VERSION;
+    use crate::events::Event;
+    use crate::events::EventQueue;
+    use crate::gamepad::GamepadButton;
+    use crate::map::GameMap;
+    use crate::recording::Recorder;
+    use crate::recording::RecordingHeader;
+    use crate::recording::Replay;
+    use crate::recording::ReplayState;
+    use crate::ui::DialogBoxConf;
+    use crate::ui::UiState;
+    use macroquad::prelude::KeyCode;
+    use specs::Entity;
+    use specs::Join;
+
+    fn walk(simulation: &mut Simulation, key: KeyCode, tiles: usize) {
+        for _ in 0..tiles {
+            simulation.step(Input::default().press(key));
+            simulation.wait_for_player();
+        }
+    }
+
+    // puts the player somewhere else on the current level, as if they had walked there
+    fn place_player(simulation: &mut Simulation, x: f32, y: f32) {
+        let player_entity = simulation.world.read_resource::<PlayerEntity>().entity;
+        simulation
+            .world
+            .write_storage::<GridPosition>()
+            .insert(player_entity, GridPosition { x, y })
+            .unwrap();
+    }
+
+    #[test]
+    fn test_walk_to_urn_and_use_it() {
+        let mut simulation = Simulation::new("small_room", 1);
+        place_player(&mut simulation, 6., 3.);
+
+        walk(&mut simulation, KeyCode::Right, 3);
+        assert_eq!(simulation.player_position(), GridPosition { x: 9., y: 3. });
+        let player_entity = simulation.world.read_resource::<PlayerEntity>().entity;
+        assert_eq!(
+            simulation
+                .world
+                .read_storage::<FacingDirection>()
+                .get(player_entity)
+                .unwrap()
+                .direction,
+            Direction::Right
+        );
+
+        // the urn is on the next tile to the right, and blocks movement
+        walk(&mut simulation, KeyCode::Right, 1);
+        assert_eq!(simulation.player_position(), GridPosition { x: 9., y: 3. });
+        simulation.step(Input::default().press(KeyCode::Space));
+        let dialog_box = simulation
+            .world
+            .read_resource::<UiState>()
+            .dialog_box_state()
+            .expect("using the urn should open a dialog");
+        assert_eq!(dialog_box.conf.message, "urn.snakes");
+    }
+
+    #[test]
+    fn test_gamepad_walk_to_urn_and_use_it() {
+        let mut simulation = Simulation::new("small_room", 1);
+        place_player(&mut simulation, 6., 3.);
+
+        // the stick moves the player like the arrow keys, and the south button uses things
+        for _ in 0..4 {
+            simulation.step(Input::default().press_button(GamepadButton::StickRight));
+            simulation.wait_for_player();
+        }
+        assert_eq!(simulation.player_position(), GridPosition { x: 9., y: 3. });
+        simulation.step(Input::default().press_button(GamepadButton::South));
+        let dialog_box = simulation
+            .world
+            .read_resource::<UiState>()
+            .dialog_box_state()
+            .expect("using the urn should open a dialog");
+        assert_eq!(dialog_box.conf.message, "urn.snakes");
+    }
+
+    // clicks the middle of a tile, as it's currently shown on screen
+    fn click_tile(simulation: &mut Simulation, x: f32, y: f32) {
+        let (screen_x, screen_y) = {
+            let map = simulation.world.read_resource::<GameMap>();
+            simulation.world.read_resource::<Camera>().grid_to_screen(
+                x + 0.5,
+                y + 0.5,
+                map.tile_width,
+                map.tile_height,
+            )
+        };
+        simulation.step(Input::default().click_at(screen_x, screen_y));
+    }
+
+    #[test]
+    fn test_click_urn_to_walk_there_and_use_it() {
+        let mut simulation = Simulation::new("small_room", 1);
+        simulation.step(Input::default());
+        place_player(&mut simulation, 6., 3.);
+
+        // the urn blocks movement, so the player walks up to it instead, and uses it
+        click_tile(&mut simulation, 10., 3.);
+        for _ in 0..600 {
+            if simulation
+                .world
+                .read_resource::<UiState>()
+                .dialog_box_state()
+                .is_some()
+            {
+                break;
+            }
+            simulation.step(Input::default());
+        }
+        assert_eq!(simulation.player_position(), GridPosition { x: 9., y: 3. });
+        let dialog_box = simulation
+            .world
+            .read_resource::<UiState>()
+            .dialog_box_state()
+            .expect("clicking the urn should walk up to it and use it");
+        assert_eq!(dialog_box.conf.message, "urn.snakes");
+
+        // clicking again continues the dialog, rather than walking anywhere
+        click_tile(&mut simulation, 6., 3.);
+        assert_eq!(simulation.player_position(), GridPosition { x: 9., y: 3. });
+    }
+
+    // the entity spawned from the map object with the given PersistentId
+    fn find_entity(simulation: &Simulation, id: &str) -> Entity {
+        let entities = simulation.world.entities();
+        let persistent_ids = simulation.world.read_storage::<PersistentId>();
+        (&entities, &persistent_ids)
+            .join()
+            .find(|(_, persistent_id)| persistent_id.id == id)
+            .map(|(entity, _)| entity)
+            .unwrap_or_else(|| panic!("no entity {}", id))
+    }
+
+    #[test]
+    fn test_walk_villager_somewhere() {
+        let mut simulation = Simulation::new("small_room", 3);
+        simulation
+            .world
+            .write_resource::<ActionQueue>()
+            .actions
+            .push(Action::WalkTo {
+                id: "small_room/7".to_owned(),
+                position: GridPosition { x: 11., y: 10. },
+                facing: Some(Direction::Left),
+            });
+        let villager = find_entity(&simulation, "small_room/7");
+
+        // the villager stops strolling, walks over to the bottom right of the room, and turns left
+        simulation.step(Input::default());
+        for _ in 0..600 {
+            if simulation
+                .world
+                .read_storage::<WalkingPath>()
+                .get(villager)
+                .is_none()
+            {
+                break;
+            }
+            simulation.step(Input::default());
+        }
+        assert!(simulation
+            .world
+            .read_storage::<WalkingPath>()
+            .get(villager)
+            .is_none());
+        assert_eq!(
+            simulation
+                .world
+                .read_storage::<GridPosition>()
+                .get(villager),
+            Some(&GridPosition { x: 11., y: 10. })
+        );
+        assert_eq!(
+            simulation
+                .world
+                .read_storage::<FacingDirection>()
+                .get(villager)
+                .map(|facing| facing.direction),
+            Some(Direction::Left)
+        );
+    }
+
+    // runs frames until the entity is standing on the given tile, and returns which way it faces there
+    fn wait_until_at(simulation: &mut Simulation, entity: Entity, x: f32, y: f32) -> Direction {
+        for _ in 0..600 {
+            simulation.step(Input::default());
+            if simulation.world.read_storage::<GridPosition>().get(entity)
+                == Some(&GridPosition { x, y })
+            {
+                break;
+            }
+        }
+        assert_eq!(
+            simulation.world.read_storage::<GridPosition>().get(entity),
+            Some(&GridPosition { x, y })
+        );
+        simulation
+            .world
+            .read_storage::<FacingDirection>()
+            .get(entity)
+            .unwrap()
+            .direction
+    }
+
+    #[test]
+    fn test_patrol_between_waypoints() {
+        let mut simulation = Simulation::new("small_room", 5);
+        let innkeeper = find_entity(&simulation, "small_room/6");
+        let waypoint = |x, facing| Waypoint {
+            x,
+            y: 4.,
+            wait_seconds: 0.5,
+            facing: Some(facing),
+        };
+        simulation
+            .world
+            .write_storage::<Patrol>()
+            .insert(
+                innkeeper,
+                Patrol {
+                    route: vec![waypoint(3., Direction::Left), waypoint(6., Direction::Up)],
+                    schedule: vec![],
+                    next_waypoint: 1,
+                    remaining_wait_seconds: 0.,
+                    walking: false,
+                },
+            )
+            .unwrap();
+
+        // back and forth along the row in front of the innkeeper's spot, turning at each end
+        wait_until_at(&mut simulation, innkeeper, 6., 4.);
+        for _ in 0..10 {
+            simulation.step(Input::default());
+        }
+        let facing = simulation
+            .world
+            .read_storage::<FacingDirection>()
+            .get(innkeeper)
+            .unwrap()
+            .direction;
+        assert_eq!(facing, Direction::Up);
+        wait_until_at(&mut simulation, innkeeper, 3., 4.);
+        wait_until_at(&mut simulation, innkeeper, 6., 4.);
+    }
+
+    #[test]
+    fn test_walk_player_during_dialog() {
+        let mut simulation = Simulation::new("small_room", 1);
+        simulation.step(Input::default());
+        place_player(&mut simulation, 6., 3.);
+        {
+            let mut action_queue = simulation.world.write_resource::<ActionQueue>();
+            action_queue.actions.push(Action::ShowDialog(DialogBoxConf {
+                message: "urn.snakes".to_owned(),
+                ..Default::default()
+            }));
+            action_queue.actions.push(Action::WalkTo {
+                id: "player".to_owned(),
+                position: GridPosition { x: 3., y: 10. },
+                facing: None,
+            });
+        }
+
+        // unlike a clicked path, the dialog doesn't stop the walk
+        let player_entity = simulation.world.read_resource::<PlayerEntity>().entity;
+        for _ in 0..600 {
+            if simulation
+                .world
+                .read_storage::<WalkingPath>()
+                .get(player_entity)
+                .is_none()
+            {
+                break;
+            }
+            simulation.step(Input::default());
+        }
+        assert!(simulation
+            .world
+            .read_resource::<UiState>()
+            .dialog_box_state()
+            .is_some());
+        assert_eq!(simulation.player_position(), GridPosition { x: 3., y: 10. });
+    }
+
+    #[test]
+    fn test_same_seed_same_game() {
+        // nothing but the strolling villager moves, so its path only depends on the seed
+        let run = |seed| {
+            let mut simulation = Simulation::new("small_room", seed);
+            let mut positions = vec![];
+            for _ in 0..600 {
+                simulation.step(Input::default());
+                let world = &simulation.world;
+                let persistent_ids = world.read_storage::<PersistentId>();
+                let grid_positions = world.read_storage::<GridPosition>();
+                positions.push(
+                    (&persistent_ids, &grid_positions)
+                        .join()
+                        .map(|(id, position)| (id.id.to_owned(), *position))
+                        .collect::<Vec<_>>(),
+                );
+            }
+            positions
+        };
+        assert_eq!(run(7), run(7));
+    }
+
+    // what a recording has to reproduce: where everything is, and what happened, on every frame
+    fn snapshot(simulation: &Simulation) -> (Vec<(String, GridPosition)>, Vec<Event>) {
+        let world = &simulation.world;
+        let persistent_ids = world.read_storage::<PersistentId>();
+        let grid_positions = world.read_storage::<GridPosition>();
+        let positions = (&persistent_ids, &grid_positions)
+            .join()
+            .map(|(id, position)| (id.id.to_owned(), *position))
+            .collect();
+        let events = world.read_resource::<EventQueue>().events.to_vec();
+        (positions, events)
+    }
+
+    #[test]
+    fn test_replay_reproduces_session() {
+        let mut recorded = Simulation::new("small_room", 99);
+        let header = RecordingHeader {
+            version: RECORDING_VERSION,
+            level_id: "small_room".to_owned(),
+            seed: 99,
+            key_bindings: recorded.world.read_resource::<KeyBindings>().to_file(),
+            gamepad: recorded
+                .world
+                .read_resource::<KeyBindings>()
+                .gamepad
+                .clone(),
+        };
+        // uneven frame times, like a real session
+        let mut frame_times = Rng::new(5);
+        let mut frames = vec![];
+        for key in [KeyCode::Right, KeyCode::Down, KeyCode::Down, KeyCode::Right].iter() {
+            frames.push(Input::default().press(*key));
+            for _ in 0..20 {
+                frames.push(Input::default().hold(*key));
+            }
+            for _ in 0..10 {
+                frames.push(Input::default());
+            }
+        }
+        frames.push(Input::default().press(KeyCode::Space));
+
+        let mut recorder = Recorder::new(vec![], &header).unwrap();
+        let mut recorded_snapshots = vec![];
+        for input in frames {
+            let delta_seconds = frame_times.gen_range_f32(0.01, 0.04);
+            recorder.record(delta_seconds, &input).unwrap();
+            recorded.step_with_delta(delta_seconds, input);
+            recorded_snapshots.push(snapshot(&recorded));
+        }
+        let recording = String::from_utf8(recorder.into_inner()).unwrap();
+
+        let mut replay = Replay::parse(&recording).unwrap();
+        let mut replayed = Simulation::new(&replay.header.level_id, replay.header.seed);
+        replayed.world.write_resource::<ReplayState>().replaying = true;
+        let mut replayed_snapshots = vec![];
+        while let Some((delta_seconds, input)) = replay.next_frame() {
+            replayed.step_with_delta(delta_seconds, input);
+            replayed_snapshots.push(snapshot(&replayed));
+        }
+        assert_eq!(replayed_snapshots, recorded_snapshots);
+        assert_eq!(replayed.player_position(), recorded.player_position());
+    }
+
+    #[test]
+    fn test_replay_leaves_save_slots_alone() {
+        // whatever is in the player's save slots, loading one during a replay does nothing
+        let mut simulation = Simulation::new("small_room", 1);
+        simulation.world.write_resource::<ReplayState>().replaying = true;
+        place_player(&mut simulation, 6., 3.);
+        walk(&mut simulation, KeyCode::Right, 1);
+        simulation.step(Input::default().press(KeyCode::F9));
+        simulation.wait_for_player();
+        assert_eq!(simulation.player_position(), GridPosition { x: 7., y: 3. });
+    }
+}
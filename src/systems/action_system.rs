@@ -1,14 +1,26 @@
 use crate::actions::Action;
+use crate::actions::ActionQueue;
+use crate::actions::FiredTriggers;
+use crate::components::Elevation;
 use crate::components::FacingDirection;
 use crate::components::GridPosition;
+use crate::components::PersistentId;
 use crate::components::PlayerEntity;
+use crate::components::TriggerActionOnDialogChoice;
 use crate::components::TriggerActionOnEnter;
 use crate::components::TriggerActionOnExit;
 use crate::components::TriggerActionOnUse;
+use crate::components::TriggerOnce;
+use crate::components::WalkingPath;
+use crate::dialogs::DialogTrees;
 use crate::events::Event;
 use crate::events::EventQueue;
+use crate::level::LevelChange;
+use crate::level::PendingLevelChange;
 use crate::ui::DialogBoxConf;
 use crate::ui::UiState;
+use specs::Entities;
+use specs::Entity;
 use specs::Join;
 use specs::ReadExpect;
 use specs::ReadStorage;
@@ -22,44 +34,87 @@ impl<'a> System<'a> for ActionSystem {
     #[allow(clippy::type_complexity)]
     type SystemData = (
         ReadExpect<'a, EventQueue>,
+        WriteExpect<'a, ActionQueue>,
+        ReadExpect<'a, DialogTrees>,
         ReadExpect<'a, PlayerEntity>,
+        WriteExpect<'a, FiredTriggers>,
+        Entities<'a>,
+        ReadStorage<'a, PersistentId>,
+        ReadStorage<'a, TriggerOnce>,
         ReadStorage<'a, TriggerActionOnEnter>,
         ReadStorage<'a, TriggerActionOnExit>,
         ReadStorage<'a, TriggerActionOnUse>,
+        ReadStorage<'a, TriggerActionOnDialogChoice>,
         ReadStorage<'a, FacingDirection>,
         WriteStorage<'a, GridPosition>,
+        WriteStorage<'a, Elevation>,
+        WriteStorage<'a, WalkingPath>,
         WriteExpect<'a, UiState>,
+        WriteExpect<'a, PendingLevelChange>,
     );
 
     // RW: For now, putting all action handling in one system. This will probably change in the future.
     fn run(&mut self, data: Self::SystemData) {
         let (
             event_queue,
+            mut action_queue,
+            dialog_trees,
             player_entity,
+            mut fired_triggers,
+            entities,
+            persistent_ids,
+            trigger_once,
             enter_triggers,
             exit_triggers,
             use_triggers,
+            dialog_choice_triggers,
             facing_directions,
             mut positions,
+            mut elevations,
+            mut walking_paths,
             mut ui_state,
+            mut pending_level_change,
         ) = data;
 
-        // Process all events, to determine which actions were triggered
-        let mut actions: Vec<Action> = vec![];
+        // Start with any actions that were queued up directly, then process all events to determine which actions were triggered
+        let mut actions: Vec<Action> = action_queue.actions.drain(..).collect();
+        // the player only triggers things on their own elevation.
+        // this is checked before any of the actions run, so stairs that change the elevation can't set off triggers on the new one.
+        let player_elevation = Elevation::index_of(elevations.get(player_entity.entity));
         for event in event_queue.events.iter() {
             match event {
                 Event::PlayerEntered(pos) => {
                     // look for any triggers that happen on player enter
-                    for (enter_action, trigger_pos) in (&enter_triggers, &positions).join() {
-                        if trigger_pos == pos {
+                    for (entity, enter_action, trigger_pos, trigger_elevation) in
+                        (&entities, &enter_triggers, &positions, elevations.maybe()).join()
+                    {
+                        if trigger_pos == pos
+                            && Elevation::index_of(trigger_elevation) == player_elevation
+                            && trigger_fires(
+                                entity,
+                                &persistent_ids,
+                                &trigger_once,
+                                &mut fired_triggers,
+                            )
+                        {
                             actions.push(enter_action.action.clone());
                         }
                     }
                 }
                 Event::PlayerExit(pos) => {
                     // look for any triggers that happen on player exit
-                    for (exit_action, trigger_pos) in (&exit_triggers, &positions).join() {
-                        if trigger_pos == pos {
+                    for (entity, exit_action, trigger_pos, trigger_elevation) in
+                        (&entities, &exit_triggers, &positions, elevations.maybe()).join()
+                    {
+                        if trigger_pos == pos
+                            && Elevation::index_of(trigger_elevation) == player_elevation
+                            && trigger_fires(
+                                entity,
+                                &persistent_ids,
+                                &trigger_once,
+                                &mut fired_triggers,
+                            )
+                        {
                             actions.push(exit_action.action.clone());
                         }
                     }
@@ -73,13 +128,33 @@ impl<'a> System<'a> for ActionSystem {
                             x: player_position.x + player_facing_direction.direction.get_delta_x(),
                             y: player_position.y + player_facing_direction.direction.get_delta_y(),
                         };
-                        for (use_action, trigger_pos) in (&use_triggers, &positions).join() {
-                            if *trigger_pos == use_position {
+                        for (entity, use_action, trigger_pos, trigger_elevation) in
+                            (&entities, &use_triggers, &positions, elevations.maybe()).join()
+                        {
+                            if *trigger_pos == use_position
+                                && Elevation::index_of(trigger_elevation) == player_elevation
+                                && trigger_fires(
+                                    entity,
+                                    &persistent_ids,
+                                    &trigger_once,
+                                    &mut fired_triggers,
+                                )
+                            {
                                 actions.push(use_action.action.clone());
                             }
                         }
                     }
                 }
+                Event::DialogChoiceSelected(dialog_id, choice) => {
+                    // look for any triggers waiting on this dialog's answer
+                    for choice_trigger in (&dialog_choice_triggers).join() {
+                        if choice_trigger.dialog_id == *dialog_id {
+                            if let Some(action) = choice_trigger.actions.get(*choice) {
+                                actions.push(action.clone());
+                            }
+                        }
+                    }
+                }
                 _ => (),
             }
         }
@@ -94,6 +169,22 @@ impl<'a> System<'a> for ActionSystem {
                         player_position.y = pos.y;
                     }
                 }
+                Action::ChangeElevation(index) => {
+                    elevations
+                        .insert(player_entity.entity, Elevation { index })
+                        .expect("failed to change player elevation");
+                }
+                Action::ChangeLevel {
+                    level_id,
+                    position,
+                    facing,
+                } => {
+                    pending_level_change.level_change = Some(LevelChange {
+                        level_id,
+                        position,
+                        facing,
+                    });
+                }
                 Action::ShowSimpleDialog(message) => {
                     ui_state.create_dialog_box(DialogBoxConf {
                         message,
@@ -103,6 +194,33 @@ impl<'a> System<'a> for ActionSystem {
                 Action::ShowDialog(dialog_conf) => {
                     ui_state.create_dialog_box(dialog_conf);
                 }
+                Action::StartConversation(node_id) => {
+                    ui_state.start_conversation(&node_id, &dialog_trees);
+                }
+                Action::WalkTo {
+                    id,
+                    position,
+                    facing,
+                } => {
+                    let walker = (&entities, &persistent_ids)
+                        .join()
+                        .find(|(_, persistent_id)| persistent_id.id == id)
+                        .map(|(entity, _)| entity);
+                    match walker {
+                        Some(walker) => {
+                            walking_paths
+                                .insert(
+                                    walker,
+                                    WalkingPath {
+                                        facing,
+                                        ..WalkingPath::to(position)
+                                    },
+                                )
+                                .expect("failed to insert walking path");
+                        }
+                        None => println!("WARNING: no one with id {} to walk", id),
+                    }
+                }
                 Action::PrintMessage(message) => {
                     println!("PRINT MESSAGE action: {}", message);
                 }
@@ -110,3 +228,22 @@ impl<'a> System<'a> for ActionSystem {
         }
     }
 }
+
+// checks whether the trigger on the given entity is allowed to fire (i.e. it isn't a TriggerOnce that already fired),
+// and if so records that it did
+fn trigger_fires(
+    entity: Entity,
+    persistent_ids: &ReadStorage<PersistentId>,
+    trigger_once: &ReadStorage<TriggerOnce>,
+    fired_triggers: &mut FiredTriggers,
+) -> bool {
+    if let Some(persistent_id) = persistent_ids.get(entity) {
+        if trigger_once.get(entity).is_some() && fired_triggers.ids.contains(&persistent_id.id) {
+            return false;
+        }
+        if trigger_once.get(entity).is_some() {
+            fired_triggers.ids.insert(persistent_id.id.to_owned());
+        }
+    }
+    true
+}
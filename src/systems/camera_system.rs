@@ -0,0 +1,28 @@
+use crate::camera::Camera;
+use crate::components::GridPosition;
+use crate::components::PlayerEntity;
+use crate::map::GameMap;
+use specs::ReadExpect;
+use specs::ReadStorage;
+use specs::System;
+use specs::WriteExpect;
+
+// This system keeps the camera following the player, so that maps larger than the window can be explored.
+pub struct CameraSystem;
+
+impl<'a> System<'a> for CameraSystem {
+    type SystemData = (
+        WriteExpect<'a, Camera>,
+        ReadExpect<'a, GameMap>,
+        ReadExpect<'a, PlayerEntity>,
+        ReadStorage<'a, GridPosition>,
+    );
+
+    fn run(&mut self, data: Self::SystemData) {
+        let (mut camera, map, player_entity, positions) = data;
+
+        if let Some(player_position) = positions.get(player_entity.entity) {
+            camera.follow(player_position.x, player_position.y, map.width, map.height);
+        }
+    }
+}
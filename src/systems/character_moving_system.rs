@@ -1,5 +1,6 @@
 use crate::components::AwaitingInputState;
 use crate::components::BlocksMovement;
+use crate::components::Elevation;
 use crate::components::EntityMovingState;
 use crate::components::FacingDirection;
 use crate::components::GridPosition;
@@ -8,9 +9,9 @@ use crate::constants::*;
 use crate::events::Event;
 use crate::events::EventQueue;
 use crate::map::GameMap;
+use crate::platform::Time;
 use crate::util::f32_eq;
 use crate::PlayerEntity;
-use macroquad::prelude::get_frame_time;
 use specs::Entities;
 use specs::Entity;
 use specs::Join;
@@ -22,7 +23,7 @@ use specs::WriteStorage;
 
 // This system is responsible for all character movement.
 // Currently, this includes:
-// 1) listening for EntityTriesMove event and puts that entity in EntityMovingState if moving to a valid location
+// 1) listening for EntityTriesMove event and puts that entity in EntityMovingState if moving to a valid location (and EntityTurns, which only turns it)
 // 2) handling the animation that occurs when an entity is in EntityMovingState
 // 3) fires events for PlayerExit and PlayerEnter for the old and new positions, if the entity that moved was the PlayerEntity
 
@@ -33,9 +34,11 @@ impl<'a> System<'a> for CharacterMovingSystem {
     type SystemData = (
         WriteExpect<'a, EventQueue>,
         ReadExpect<'a, GameMap>,
+        ReadExpect<'a, Time>,
         ReadExpect<'a, PlayerEntity>,
         Entities<'a>,
         ReadStorage<'a, BlocksMovement>,
+        ReadStorage<'a, Elevation>,
         WriteStorage<'a, AwaitingInputState>,
         WriteStorage<'a, EntityMovingState>,
         WriteStorage<'a, GridPosition>,
@@ -44,24 +47,33 @@ impl<'a> System<'a> for CharacterMovingSystem {
     );
 
     fn run(&mut self, data: Self::SystemData) {
-        let delta_time = get_frame_time();
-
         let (
             mut event_queue,
             map,
+            time,
             player_entity,
             entities,
             blocks_movement,
+            elevations,
             mut awaiting_input_states,
             mut entity_moving_states,
             mut positions,
             mut drawables,
             mut facing_directions,
         ) = data;
+        let delta_time = time.delta_seconds;
 
         // Handle events: EntityTriesMove
         let mut new_events: Vec<Event> = vec![];
         for event in event_queue.events.iter() {
+            if let Event::EntityTurns(entity, direction) = event {
+                if let Some(facing_direction) = facing_directions.get_mut(*entity) {
+                    facing_direction.direction = *direction;
+                }
+                if let Some(drawable) = drawables.get_mut(*entity) {
+                    drawable.current_frame = direction.get_character_facing_frame();
+                }
+            }
             if let Event::EntityTriesMove(entity, direction) = event {
                 if let (Some(drawable), Some(position)) =
                     (drawables.get_mut(*entity), positions.get(*entity))
@@ -71,6 +83,8 @@ impl<'a> System<'a> for CharacterMovingSystem {
                     let mut moving = false;
                     let new_x = position.x + delta_x;
                     let new_y = position.y + delta_y;
+                    // entities only collide with the map and other entities on their own elevation
+                    let elevation = Elevation::index_of(elevations.get(*entity));
                     println!(
                         "current position = {:?}, trying new position = {},{}, facing: {:?}",
                         *position, new_x, new_y, direction
@@ -80,30 +94,34 @@ impl<'a> System<'a> for CharacterMovingSystem {
                         moving = true;
                     }
                     // check if the new location is actually somewhere we can move
-                    if map.is_blocked(new_x, new_y) {
+                    if map.is_blocked(new_x, new_y, elevation) {
                         moving = false;
                     }
                     // check if any other entity that blocks movement is in new_x/new_y position
-                    for (other_entity_position, _blocks_movement) in
-                        (&positions, &blocks_movement).join()
+                    for (other_entity_position, _blocks_movement, other_elevation) in
+                        (&positions, &blocks_movement, elevations.maybe()).join()
                     {
-                        if f32_eq(other_entity_position.x, new_x)
+                        if Elevation::index_of(other_elevation) == elevation
+                            && f32_eq(other_entity_position.x, new_x)
                             && f32_eq(other_entity_position.y, new_y)
                         {
                             moving = false;
                         }
                     }
                     // check if any moving entity is currently moving headed toward new_x/new_y position
-                    for (other_entity_position, entity_moving_state) in
-                        (&positions, &entity_moving_states).join()
+                    for (other_entity_position, entity_moving_state, other_elevation) in
+                        (&positions, &entity_moving_states, elevations.maybe()).join()
                     {
-                        if f32_eq(
-                            (other_entity_position.x + entity_moving_state.delta_x).round(),
-                            new_x,
-                        ) && f32_eq(
-                            (other_entity_position.y + entity_moving_state.delta_y).round(),
-                            new_y,
-                        ) {
+                        if Elevation::index_of(other_elevation) == elevation
+                            && f32_eq(
+                                (other_entity_position.x + entity_moving_state.delta_x).round(),
+                                new_x,
+                            )
+                            && f32_eq(
+                                (other_entity_position.y + entity_moving_state.delta_y).round(),
+                                new_y,
+                            )
+                        {
                             moving = false;
                         }
                     }
@@ -0,0 +1,17 @@
+use crate::clock::GameClock;
+use crate::platform::Time;
+use specs::ReadExpect;
+use specs::System;
+use specs::WriteExpect;
+
+// moves the game's time of day along with the frame time
+pub struct ClockSystem;
+
+impl<'a> System<'a> for ClockSystem {
+    type SystemData = (ReadExpect<'a, Time>, WriteExpect<'a, GameClock>);
+
+    fn run(&mut self, data: Self::SystemData) {
+        let (time, mut clock) = data;
+        clock.advance(time.delta_seconds);
+    }
+}
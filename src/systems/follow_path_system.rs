@@ -0,0 +1,283 @@
+use crate::components::BlocksMovement;
+use crate::components::Elevation;
+use crate::components::FacingDirection;
+use crate::components::GridPosition;
+use crate::components::WalkingPath;
+use crate::constants::WALKING_PATH_PATIENCE_SECONDS;
+use crate::events::Event;
+use crate::pathfinding::direction_to;
+use crate::pathfinding::occupied_tiles;
+use crate::pathfinding::tile_of;
+use crate::pathfinding::Pathfinder;
+use crate::platform::Time;
+use crate::Direction;
+use crate::EntityMovingState;
+use crate::EventQueue;
+use crate::PlayerEntity;
+use specs::Entities;
+use specs::Entity;
+use specs::Join;
+use specs::ReadExpect;
+use specs::ReadStorage;
+use specs::System;
+use specs::WriteExpect;
+use specs::WriteStorage;
+
+// This system walks every entity with a WalkingPath towards its destination, one EntityTriesMove at a time, just like
+// the player holding the keys or PlanStrollSystem picking a direction. The steps are planned with the Pathfinder when the
+// path is first followed, and again whenever something steps into the way.
+pub struct FollowPathSystem;
+
+impl<'a> System<'a> for FollowPathSystem {
+    #[allow(clippy::type_complexity)]
+    type SystemData = (
+        WriteExpect<'a, EventQueue>,
+        ReadExpect<'a, Time>,
+        ReadExpect<'a, Pathfinder>,
+        ReadExpect<'a, PlayerEntity>,
+        Entities<'a>,
+        ReadStorage<'a, GridPosition>,
+        ReadStorage<'a, Elevation>,
+        ReadStorage<'a, BlocksMovement>,
+        ReadStorage<'a, EntityMovingState>,
+        ReadStorage<'a, FacingDirection>,
+        WriteStorage<'a, WalkingPath>,
+    );
+
+    fn run(&mut self, data: Self::SystemData) {
+        let (
+            mut event_queue,
+            time,
+            pathfinder,
+            player_entity,
+            entities,
+            positions,
+            elevations,
+            blocks_movement,
+            entity_moving_states,
+            facing_directions,
+            mut walking_paths,
+        ) = data;
+
+        let mut finished = vec![];
+        for (entity, path, position, _) in (
+            &entities,
+            &mut walking_paths,
+            &positions,
+            !&entity_moving_states,
+        )
+            .join()
+        {
+            let elevation = Elevation::index_of(elevations.get(entity));
+            let occupied = occupied_tiles(
+                entity,
+                elevation,
+                &entities,
+                &positions,
+                &elevations,
+                &blocks_movement,
+                &entity_moving_states,
+            );
+            let facing = facing_directions.get(entity).map(|facing| facing.direction);
+            let (event, path_done) = follow_path(
+                entity,
+                entity == player_entity.entity,
+                path,
+                position,
+                facing,
+                time.delta_seconds,
+                |from, path| {
+                    pathfinder.find_path(
+                        from,
+                        &path.destination,
+                        path.use_destination,
+                        elevation,
+                        &occupied,
+                    )
+                },
+                |tile| pathfinder.is_blocked(tile, elevation, &occupied),
+            );
+            if let Some(event) = event {
+                event_queue.events.push(event);
+            }
+            if path_done {
+                finished.push(entity);
+            }
+        }
+        for entity in finished {
+            walking_paths.remove(entity);
+        }
+    }
+}
+
+// what the entity does next along the path, and whether the path is finished with
+#[allow(clippy::too_many_arguments)]
+fn follow_path<P, B>(
+    entity: Entity,
+    is_player: bool,
+    path: &mut WalkingPath,
+    position: &GridPosition,
+    facing: Option<Direction>,
+    delta_seconds: f32,
+    plan: P,
+    is_blocked: B,
+) -> (Option<Event>, bool)
+where
+    P: Fn(&GridPosition, &WalkingPath) -> Option<Vec<GridPosition>>,
+    B: Fn((i32, i32)) -> bool,
+{
+    let tile = tile_of(position);
+    let destination = tile_of(&path.destination);
+    // steps that have been taken already. a step that didn't happen (e.g. someone stepped in first) stays, and is tried again.
+    while path.steps.last().map(tile_of) == Some(tile) {
+        path.steps.pop();
+    }
+
+    let arrived = if path.use_destination {
+        direction_to(tile, destination).is_some()
+    } else {
+        tile == destination
+    };
+    if arrived {
+        // turn to face whatever is at the destination, or whichever way the path says, then use it if that's the plan
+        let direction = if path.use_destination {
+            direction_to(tile, destination)
+        } else {
+            path.facing
+        };
+        return match direction {
+            // something without a FacingDirection can't turn, so it's done as soon as it gets there
+            Some(direction) if facing.map_or(false, |facing| facing != direction) => {
+                (Some(Event::EntityTurns(entity, direction)), false)
+            }
+            _ if path.use_destination && is_player => (Some(Event::PlayerTriesUse()), true),
+            _ => (None, true),
+        };
+    }
+
+    let needs_plan = match path.steps.last() {
+        Some(step) => is_blocked(tile_of(step)),
+        None => true,
+    };
+    if needs_plan {
+        // not planned yet, or something moved into the way, so look for a way (around it). if there isn't one, wait a bit.
+        match plan(position, path) {
+            Some(steps) => {
+                path.steps = steps.into_iter().rev().collect();
+                path.blocked_seconds = 0.;
+            }
+            None => {
+                path.blocked_seconds += delta_seconds;
+                return (None, path.blocked_seconds > WALKING_PATH_PATIENCE_SECONDS);
+            }
+        }
+    }
+
+    match path
+        .steps
+        .last()
+        .and_then(|step| direction_to(tile, tile_of(step)))
+    {
+        Some(direction) => (Some(Event::EntityTriesMove(entity, direction)), false),
+        // not next to the path any more, e.g. a trigger teleported the player
+        None => (None, true),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use specs::World;
+    use specs::WorldExt;
+
+    #[test]
+    fn test_follow_path() {
+        let mut world = World::new();
+        let entity = world.create_entity().build();
+        let mut path = WalkingPath {
+            facing: Some(Direction::Up),
+            ..WalkingPath::to(GridPosition { x: 2., y: 0. })
+        };
+        let mut position = GridPosition { x: 0., y: 0. };
+        // a straight line along the top row
+        let straight_line = |from: &GridPosition, _: &WalkingPath| {
+            Some(
+                ((from.x as i32 + 1)..=2)
+                    .map(|x| GridPosition { x: x as f32, y: 0. })
+                    .collect(),
+            )
+        };
+
+        let (event, done) = follow_path(
+            entity,
+            false,
+            &mut path,
+            &position,
+            Some(Direction::Down),
+            0.1,
+            straight_line,
+            |_| false,
+        );
+        assert!(matches!(
+            event,
+            Some(Event::EntityTriesMove(_, Direction::Right))
+        ));
+        assert!(!done);
+        assert_eq!(path.steps.len(), 2);
+
+        // arriving turns the entity the way the path says, and then it's done
+        position.x = 2.;
+        let (event, done) = follow_path(
+            entity,
+            false,
+            &mut path,
+            &position,
+            Some(Direction::Right),
+            0.1,
+            straight_line,
+            |_| false,
+        );
+        assert!(matches!(event, Some(Event::EntityTurns(_, Direction::Up))));
+        assert!(!done);
+        let (event, done) = follow_path(
+            entity,
+            false,
+            &mut path,
+            &position,
+            Some(Direction::Up),
+            0.1,
+            straight_line,
+            |_| false,
+        );
+        assert!(event.is_none());
+        assert!(done);
+    }
+
+    #[test]
+    fn test_give_up_when_blocked() {
+        let mut world = World::new();
+        let entity = world.create_entity().build();
+        let mut path = WalkingPath::to(GridPosition { x: 5., y: 5. });
+        let position = GridPosition { x: 0., y: 0. };
+        let mut waited = 0.;
+        loop {
+            let (event, done) = follow_path(
+                entity,
+                false,
+                &mut path,
+                &position,
+                None,
+                0.5,
+                |_, _| None,
+                |_| true,
+            );
+            assert!(event.is_none());
+            if done {
+                break;
+            }
+            waited += 0.5;
+        }
+        assert_eq!(waited, WALKING_PATH_PATIENCE_SECONDS);
+    }
+}
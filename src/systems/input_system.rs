@@ -1,63 +1,170 @@
+use crate::bindings::InputAction;
+use crate::bindings::KeyBindings;
+use crate::camera::Camera;
+use crate::components::Elevation;
+use crate::components::GridPosition;
+use crate::components::TriggerActionOnUse;
+use crate::components::WalkingPath;
+use crate::constants::SAVE_SLOTS;
 use crate::events::Event;
+use crate::map::GameMap;
+use crate::platform::Input;
+use crate::save::SaveRequest;
+use crate::save::SaveSlots;
 use crate::AwaitingInputState;
 use crate::Direction;
 use crate::EventQueue;
 use crate::PlayerEntity;
 use crate::UiState;
-use macroquad::input::is_key_down;
-use macroquad::input::is_key_pressed;
-use macroquad::prelude::KeyCode;
+use specs::Join;
 use specs::ReadExpect;
 use specs::ReadStorage;
 use specs::System;
 use specs::WriteExpect;
+use specs::WriteStorage;
+
+// actions that pick a save slot, in slot order
+const SAVE_SLOT_ACTIONS: [InputAction; SAVE_SLOTS] = [
+    InputAction::SaveSlot1,
+    InputAction::SaveSlot2,
+    InputAction::SaveSlot3,
+];
 
 // This InputSystem is used to handle player movement and interaction during gameplay.
+// Besides the keys (or gamepad), clicking on the map walks the player there along a WalkingPath (see FollowPathSystem).
 pub struct InputSystem;
 
 impl<'a> System<'a> for InputSystem {
+    #[allow(clippy::type_complexity)]
     type SystemData = (
         WriteExpect<'a, EventQueue>,
+        ReadExpect<'a, Input>,
+        ReadExpect<'a, KeyBindings>,
+        ReadExpect<'a, GameMap>,
+        ReadExpect<'a, Camera>,
         ReadStorage<'a, AwaitingInputState>,
         ReadExpect<'a, PlayerEntity>,
         ReadExpect<'a, UiState>,
+        WriteExpect<'a, SaveSlots>,
+        ReadStorage<'a, GridPosition>,
+        ReadStorage<'a, Elevation>,
+        ReadStorage<'a, TriggerActionOnUse>,
+        WriteStorage<'a, WalkingPath>,
     );
 
     fn run(&mut self, data: Self::SystemData) {
-        let (mut event_queue, awaiting_input_states, player_entity, ui_state) = data;
+        let (
+            mut event_queue,
+            input,
+            bindings,
+            map,
+            camera,
+            awaiting_input_states,
+            player_entity,
+            ui_state,
+            mut save_slots,
+            positions,
+            elevations,
+            use_triggers,
+            mut walking_paths,
+        ) = data;
+        let player = player_entity.entity;
+        let scripted_walk = walking_paths
+            .get(player)
+            .map_or(false, |path| !path.from_click);
 
-        // Ignore usual input if the UI System is currently in control (as signaled by UIState)
-        if ui_state.is_engaged() {
+        // the controls menu takes all the input while it's open
+        if ui_state.controls_menu.is_some() {
+            if !scripted_walk {
+                walking_paths.remove(player);
+            }
             return;
         }
 
-        if let Some(_player_awaiting_input) = awaiting_input_states.get(player_entity.entity) {
-            let mut direction: Option<Direction> = None;
-            if is_key_down(KeyCode::Left) || is_key_down(KeyCode::A) {
-                direction = Some(Direction::Left);
+        // saving and loading works during dialogs too, the open dialog box is part of the save
+        for (slot, action) in SAVE_SLOT_ACTIONS.iter().enumerate() {
+            if bindings.is_pressed(&input, *action) {
+                save_slots.current_slot = slot;
             }
+        }
+        if bindings.is_pressed(&input, InputAction::Save) {
+            save_slots.request = Some(SaveRequest::Save);
+        }
+        if bindings.is_pressed(&input, InputAction::Load) {
+            save_slots.request = Some(SaveRequest::Load);
+        }
 
-            if is_key_down(KeyCode::Right) || is_key_down(KeyCode::D) {
-                direction = Some(Direction::Right);
+        // Ignore usual input if the UI System is currently in control (as signaled by UIState)
+        // that includes a dialog popping up part way along a clicked path, which stops the player there
+        if ui_state.is_engaged() {
+            if !scripted_walk {
+                walking_paths.remove(player);
             }
+            return;
+        }
 
-            if is_key_down(KeyCode::Up) || is_key_down(KeyCode::W) {
-                direction = Some(Direction::Up);
-            }
+        // an Action::WalkTo is in control of the player until they get there, e.g. during a cutscene
+        if scripted_walk {
+            return;
+        }
 
-            if is_key_down(KeyCode::Down) || is_key_down(KeyCode::S) {
-                direction = Some(Direction::Down);
-            }
+        let elevation = Elevation::index_of(elevations.get(player));
 
-            if let Some(direction) = direction {
-                event_queue
-                    .events
-                    .push(Event::EntityTriesMove(player_entity.entity, direction));
-            }
+        // clicking on the map walks there, or if there's something to use there, walks up to it and uses it
+        let click = input.click().and_then(|(screen_x, screen_y)| {
+            camera.screen_to_grid(screen_x, screen_y, map.tile_width, map.tile_height)
+        });
+        if let Some((x, y)) = click {
+            let destination = GridPosition { x, y };
+            let use_destination = (&use_triggers, &positions, elevations.maybe()).join().any(
+                |(_, trigger_position, trigger_elevation)| {
+                    *trigger_position == destination
+                        && Elevation::index_of(trigger_elevation) == elevation
+                },
+            );
+            walking_paths
+                .insert(
+                    player,
+                    WalkingPath {
+                        use_destination,
+                        from_click: true,
+                        ..WalkingPath::to(destination)
+                    },
+                )
+                .expect("failed to insert walking path");
+        }
 
-            if is_key_pressed(KeyCode::Space) {
-                event_queue.events.push(Event::PlayerTriesUse())
-            }
+        if awaiting_input_states.get(player).is_none() {
+            return;
+        }
+
+        let mut direction: Option<Direction> = None;
+        if bindings.is_down(&input, InputAction::MoveLeft) {
+            direction = Some(Direction::Left);
+        }
+
+        if bindings.is_down(&input, InputAction::MoveRight) {
+            direction = Some(Direction::Right);
+        }
+
+        if bindings.is_down(&input, InputAction::MoveUp) {
+            direction = Some(Direction::Up);
+        }
+
+        if bindings.is_down(&input, InputAction::MoveDown) {
+            direction = Some(Direction::Down);
+        }
+
+        if let Some(direction) = direction {
+            // the keys take over from a clicked path
+            walking_paths.remove(player);
+            event_queue
+                .events
+                .push(Event::EntityTriesMove(player, direction));
+        }
+
+        if bindings.is_pressed(&input, InputAction::Use) {
+            event_queue.events.push(Event::PlayerTriesUse())
         }
     }
 }
@@ -1,13 +1,50 @@
 mod action_system;
+mod camera_system;
 mod character_moving_system;
+mod clock_system;
+mod follow_path_system;
 mod input_system;
+mod patrol_system;
 mod plan_stroll_system;
 mod rendering_system;
+mod ui_rendering_system;
 mod ui_system;
 
 pub use action_system::ActionSystem;
+pub use camera_system::CameraSystem;
 pub use character_moving_system::CharacterMovingSystem;
+pub use clock_system::ClockSystem;
+pub use follow_path_system::FollowPathSystem;
 pub use input_system::InputSystem;
+pub use patrol_system::PatrolSystem;
 pub use plan_stroll_system::PlanStrollSystem;
 pub use rendering_system::RenderingSystem;
+pub use ui_rendering_system::UiRenderingSystem;
 pub use ui_system::UiSystem;
+
+use specs::DispatcherBuilder;
+
+// every system that updates the game, without any that draw. the main loop adds the rendering systems on top,
+// while headless simulations (see simulation.rs) run these as is.
+pub fn simulation_dispatcher_builder<'a, 'b>() -> DispatcherBuilder<'a, 'b> {
+    DispatcherBuilder::new()
+        .with(ClockSystem, "clock", &[])
+        .with(InputSystem, "input", &[])
+        .with(PlanStrollSystem, "plan_stroll", &[])
+        .with(PatrolSystem, "patrol", &["clock"])
+        .with(FollowPathSystem, "follow_path", &["input", "patrol"])
+        .with(
+            CharacterMovingSystem,
+            "character_moving",
+            &["input", "plan_stroll", "follow_path"],
+        )
+        .with(ActionSystem, "action", &[])
+        .with(CameraSystem, "camera", &["character_moving", "action"])
+        .with(
+            UiSystem {
+                ..Default::default()
+            },
+            "ui",
+            &["camera"],
+        )
+}
@@ -0,0 +1,149 @@
+use crate::clock::GameClock;
+use crate::components::GridPosition;
+use crate::components::Patrol;
+use crate::components::WalkingPath;
+use crate::constants::PATROL_RETRY_SECONDS;
+use crate::pathfinding::tile_of;
+use crate::platform::Time;
+use crate::EntityMovingState;
+use specs::Entities;
+use specs::Join;
+use specs::ReadExpect;
+use specs::ReadStorage;
+use specs::System;
+use specs::WriteStorage;
+
+// This system plans the rounds of every Patrol entity, alongside PlanStrollSystem for the ones that just wander.
+// Each leg of the route is walked with a WalkingPath (see FollowPathSystem), which finds the way there and steps around
+// anyone in the way. When a leg is over, the entity is either at the waypoint, so it waits there and then heads for the
+// next one, or it gave up because the way stayed blocked, so it waits a moment and tries that waypoint again.
+// Entities with a schedule switch routes as the GameClock passes each entry's start hour.
+pub struct PatrolSystem;
+
+impl<'a> System<'a> for PatrolSystem {
+    #[allow(clippy::type_complexity)]
+    type SystemData = (
+        ReadExpect<'a, Time>,
+        ReadExpect<'a, GameClock>,
+        Entities<'a>,
+        ReadStorage<'a, GridPosition>,
+        ReadStorage<'a, EntityMovingState>,
+        WriteStorage<'a, Patrol>,
+        WriteStorage<'a, WalkingPath>,
+    );
+
+    fn run(&mut self, data: Self::SystemData) {
+        let (
+            time,
+            clock,
+            entities,
+            positions,
+            entity_moving_states,
+            mut patrols,
+            mut walking_paths,
+        ) = data;
+
+        for (entity, patrol, position) in (&entities, &mut patrols, &positions).join() {
+            let new_route = patrol
+                .scheduled_route(clock.hour())
+                .filter(|route| **route != patrol.route)
+                .cloned();
+            if let Some(route) = new_route {
+                // start the new route from the top. a leg that's under way is finished first.
+                patrol.route = route;
+                patrol.next_waypoint = 0;
+                patrol.remaining_wait_seconds = 0.;
+                patrol.walking = false;
+            }
+            if patrol.route.is_empty()
+                || walking_paths.contains(entity)
+                || entity_moving_states.contains(entity)
+            {
+                continue;
+            }
+            if patrol.remaining_wait_seconds > 0. {
+                patrol.remaining_wait_seconds -= time.delta_seconds;
+                continue;
+            }
+
+            let waypoint = patrol.route[patrol.next_waypoint % patrol.route.len()].clone();
+            let destination = GridPosition {
+                x: waypoint.x,
+                y: waypoint.y,
+            };
+            if patrol.walking {
+                patrol.walking = false;
+                if tile_of(position) == tile_of(&destination) {
+                    patrol.remaining_wait_seconds = waypoint.wait_seconds;
+                    patrol.next_waypoint = (patrol.next_waypoint + 1) % patrol.route.len();
+                } else {
+                    patrol.remaining_wait_seconds = PATROL_RETRY_SECONDS;
+                }
+            } else {
+                walking_paths
+                    .insert(
+                        entity,
+                        WalkingPath {
+                            facing: waypoint.facing,
+                            ..WalkingPath::to(destination)
+                        },
+                    )
+                    .expect("failed to insert walking path");
+                patrol.walking = true;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::components::ScheduleEntry;
+    use crate::components::Waypoint;
+
+    fn waypoint(x: f32, y: f32) -> Waypoint {
+        Waypoint {
+            x,
+            y,
+            wait_seconds: 0.,
+            facing: None,
+        }
+    }
+
+    #[test]
+    fn test_scheduled_route() {
+        let patrol = Patrol {
+            route: vec![],
+            schedule: vec![
+                ScheduleEntry {
+                    start_hour: 8.,
+                    route: vec![waypoint(1., 1.), waypoint(5., 1.)],
+                },
+                ScheduleEntry {
+                    start_hour: 20.5,
+                    route: vec![waypoint(9., 9.)],
+                },
+            ],
+            next_waypoint: 0,
+            remaining_wait_seconds: 0.,
+            walking: false,
+        };
+        assert_eq!(patrol.scheduled_route(8.).map(|route| route.len()), Some(2));
+        assert_eq!(
+            patrol.scheduled_route(20.).map(|route| route.len()),
+            Some(2)
+        );
+        assert_eq!(
+            patrol.scheduled_route(20.5).map(|route| route.len()),
+            Some(1)
+        );
+        // after midnight it's still the evening's route
+        assert_eq!(patrol.scheduled_route(3.).map(|route| route.len()), Some(1));
+        let unscheduled = Patrol {
+            schedule: vec![],
+            ..patrol
+        };
+        assert_eq!(unscheduled.scheduled_route(12.), None);
+    }
+}
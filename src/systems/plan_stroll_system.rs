@@ -1,40 +1,91 @@
 use crate::components::Direction;
+use crate::components::Elevation;
+use crate::components::GridPosition;
 use crate::components::Strolling;
+use crate::components::TriggerActionOnEnter;
+use crate::components::TriggerActionOnExit;
+use crate::components::TriggerActionOnUse;
 use crate::components::WaitingState;
+use crate::components::WalkingPath;
 use crate::events::Event;
 use crate::events::EventQueue;
+use crate::pathfinding::tile_of;
+use crate::platform::Rng;
+use crate::platform::Time;
 use crate::EntityMovingState;
-use macroquad::time::get_frame_time;
-use quad_rand as qrand;
 use specs::Entities;
 use specs::Join;
+use specs::ReadExpect;
 use specs::ReadStorage;
 use specs::System;
 use specs::WriteExpect;
 use specs::WriteStorage;
+use std::collections::HashSet;
 
 // This system is responsible for planning (basic "AI") of all Strolling entities.
 // Currently, this includes:
 // 1) managing the entity WaitingState for some amount of time
-// 2) choosing a random direction and trying to move there using an EntityTriesMove event and letting movement system handle that
+// 2) choosing a random direction and trying to move there using an EntityTriesMove event and letting movement system handle that.
+//    only directions that keep the entity inside its area, and off excluded tiles and triggers, are picked.
+// Entities that are walking somewhere (see FollowPathSystem) don't stroll until they get there.
 
 pub struct PlanStrollSystem;
 
+const DIRECTIONS: [Direction; 4] = [
+    Direction::Up,
+    Direction::Down,
+    Direction::Left,
+    Direction::Right,
+];
+
 impl<'a> System<'a> for PlanStrollSystem {
+    #[allow(clippy::type_complexity)]
     type SystemData = (
         WriteExpect<'a, EventQueue>,
+        ReadExpect<'a, Time>,
+        WriteExpect<'a, Rng>,
         Entities<'a>,
         ReadStorage<'a, Strolling>,
         ReadStorage<'a, EntityMovingState>,
+        ReadStorage<'a, WalkingPath>,
+        ReadStorage<'a, GridPosition>,
+        ReadStorage<'a, Elevation>,
+        ReadStorage<'a, TriggerActionOnEnter>,
+        ReadStorage<'a, TriggerActionOnExit>,
+        ReadStorage<'a, TriggerActionOnUse>,
         WriteStorage<'a, WaitingState>,
     );
 
     fn run(&mut self, data: Self::SystemData) {
-        let (mut event_queue, entities, strollings, entity_moving_states, mut waiting_states) =
-            data;
-        let delta_time = get_frame_time();
+        let (
+            mut event_queue,
+            time,
+            mut rng,
+            entities,
+            strollings,
+            entity_moving_states,
+            walking_paths,
+            positions,
+            elevations,
+            enter_triggers,
+            exit_triggers,
+            use_triggers,
+            mut waiting_states,
+        ) = data;
+        let delta_time = time.delta_seconds;
+
+        // tiles with triggers on them, along with their elevation, since strolling onto a door or some stairs makes no sense
+        let mut trigger_tiles = HashSet::new();
+        for (entity, position, elevation) in (&entities, &positions, elevations.maybe()).join() {
+            if enter_triggers.contains(entity)
+                || exit_triggers.contains(entity)
+                || use_triggers.contains(entity)
+            {
+                trigger_tiles.insert((tile_of(position), Elevation::index_of(elevation)));
+            }
+        }
 
-        for (entity, strolling) in (&entities, &strollings).join() {
+        for (entity, strolling, _) in (&entities, &strollings, !&walking_paths).join() {
             match (
                 waiting_states.get_mut(entity),
                 entity_moving_states.get(entity),
@@ -43,16 +94,33 @@ impl<'a> System<'a> for PlanStrollSystem {
                 (Some(waiting_state), None) => {
                     if waiting_state.remaining_wait_seconds - delta_time <= 0. {
                         // finished waiting, so pick a random direction to move
-                        let direction = match qrand::gen_range(0, 4) {
-                            0 => Direction::Up,
-                            1 => Direction::Down,
-                            2 => Direction::Left,
-                            _ => Direction::Right,
+                        let directions: Vec<Direction> = match positions.get(entity) {
+                            Some(position) => {
+                                let elevation = Elevation::index_of(elevations.get(entity));
+                                DIRECTIONS
+                                    .iter()
+                                    .copied()
+                                    .filter(|direction| {
+                                        can_stroll(
+                                            strolling,
+                                            position,
+                                            *direction,
+                                            &trigger_tiles,
+                                            elevation,
+                                        )
+                                    })
+                                    .collect()
+                            }
+                            None => DIRECTIONS.to_vec(),
                         };
-                        // start movement and stop waiting state
-                        event_queue
-                            .events
-                            .push(Event::EntityTriesMove(entity, direction));
+                        // start movement and stop waiting state. with nowhere to go, it just waits again.
+                        if !directions.is_empty() {
+                            let direction =
+                                directions[rng.gen_range(0, directions.len() as i32) as usize];
+                            event_queue
+                                .events
+                                .push(Event::EntityTriesMove(entity, direction));
+                        }
                         waiting_states.remove(entity);
                     } else {
                         waiting_state.remaining_wait_seconds -= delta_time;
@@ -65,10 +133,8 @@ impl<'a> System<'a> for PlanStrollSystem {
                         .insert(
                             entity,
                             WaitingState {
-                                remaining_wait_seconds: qrand::gen_range(
-                                    0.,
-                                    strolling.max_pause_seconds,
-                                ),
+                                remaining_wait_seconds: rng
+                                    .gen_range_f32(0., strolling.max_pause_seconds),
                             },
                         )
                         .expect("failed to insert waiting state for strolling entity");
@@ -79,3 +145,90 @@ impl<'a> System<'a> for PlanStrollSystem {
         }
     }
 }
+
+// whether a strolling entity may step in the given direction. once it's outside its area (e.g. after walking
+// somewhere), it only steps back towards the area.
+fn can_stroll(
+    strolling: &Strolling,
+    position: &GridPosition,
+    direction: Direction,
+    trigger_tiles: &HashSet<((i32, i32), usize)>,
+    elevation: usize,
+) -> bool {
+    let next = GridPosition {
+        x: position.x + direction.get_delta_x(),
+        y: position.y + direction.get_delta_y(),
+    };
+    if trigger_tiles.contains(&(tile_of(&next), elevation))
+        || strolling
+            .exclude
+            .iter()
+            .any(|excluded| tile_of(excluded) == tile_of(&next))
+    {
+        return false;
+    }
+    match &strolling.area {
+        Some(area) if area.contains(position) => area.contains(&next),
+        Some(area) => {
+            let center = area.center();
+            (center.x - position.x) * direction.get_delta_x()
+                + (center.y - position.y) * direction.get_delta_y()
+                > 0.
+        }
+        None => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::components::StrollArea;
+
+    #[test]
+    fn test_can_stroll() {
+        let strolling = Strolling {
+            max_pause_seconds: 1.,
+            area: Some(StrollArea::Rect {
+                x: 2.,
+                y: 2.,
+                width: 3.,
+                height: 2.,
+            }),
+            exclude: vec![GridPosition { x: 3., y: 2. }],
+        };
+        let mut trigger_tiles = HashSet::new();
+        trigger_tiles.insert(((2, 2), 0));
+        let allowed = |x, y, elevation| -> Vec<Direction> {
+            DIRECTIONS
+                .iter()
+                .copied()
+                .filter(|direction| {
+                    can_stroll(
+                        &strolling,
+                        &GridPosition { x, y },
+                        *direction,
+                        &trigger_tiles,
+                        elevation,
+                    )
+                })
+                .collect()
+        };
+        // the bottom left corner, with a trigger above and the edge of the area to the left and below
+        assert_eq!(allowed(2., 3., 0), vec![Direction::Right]);
+        // the trigger is on another elevation
+        assert_eq!(allowed(2., 3., 1), vec![Direction::Up, Direction::Right]);
+        // the excluded tile is to the left
+        assert_eq!(allowed(4., 2., 0), vec![Direction::Down]);
+        // outside, only back towards the middle
+        assert_eq!(allowed(7., 0., 0), vec![Direction::Down, Direction::Left]);
+
+        let circle = StrollArea::Circle {
+            x: 6.,
+            y: 8.,
+            radius: 2.,
+        };
+        assert!(circle.contains(&GridPosition { x: 8., y: 8. }));
+        assert!(!circle.contains(&GridPosition { x: 8., y: 9. }));
+    }
+}
@@ -1,20 +1,28 @@
+use crate::bindings::InputAction;
+use crate::bindings::KeyBindings;
+use crate::camera::Camera;
+use crate::components::Elevation;
 use crate::components::GridPosition;
 use crate::components::SpriteDrawable;
 use crate::constants::GLOBAL_MULTIPLIER;
 use crate::constants::GLOBAL_OFFSET_X;
 use crate::constants::GLOBAL_OFFSET_Y;
-use crate::constants::LEVEL_HEIGHT;
-use crate::constants::LEVEL_WIDTH;
 use crate::map::GameMap;
+use crate::platform::key_name;
+use crate::platform::Time;
+use crate::save::SaveSlots;
+use crate::ui::UiState;
 use core::cmp::Ordering::Equal;
+use macroquad::prelude::screen_height;
+use macroquad::prelude::screen_width;
 use macroquad::prelude::vec2;
 use macroquad::prelude::Rect;
+use macroquad::prelude::BLACK;
 use macroquad::prelude::WHITE;
+use macroquad::shapes::draw_rectangle;
 use macroquad::text::draw_text;
 use macroquad::texture::draw_texture_ex;
 use macroquad::texture::DrawTextureParams;
-use macroquad::time::get_frame_time;
-use macroquad::time::get_time;
 use specs::Join;
 use specs::ReadExpect;
 use specs::{ReadStorage, System};
@@ -28,73 +36,176 @@ pub struct RenderingSystem {
 impl<'a> System<'a> for RenderingSystem {
     type SystemData = (
         ReadExpect<'a, GameMap>,
+        ReadExpect<'a, Camera>,
+        ReadExpect<'a, Time>,
+        ReadExpect<'a, UiState>,
+        ReadExpect<'a, SaveSlots>,
+        ReadExpect<'a, KeyBindings>,
         ReadStorage<'a, GridPosition>,
         ReadStorage<'a, SpriteDrawable>,
+        ReadStorage<'a, Elevation>,
     );
 
     fn run(&mut self, data: Self::SystemData) {
-        let (map, positions, drawables) = data;
+        let (map, camera, time, ui_state, save_slots, bindings, positions, drawables, elevations) =
+            data;
 
-        // draw FPS
-        if get_time() > self.last_fps_time + 0.2 {
-            self.last_fps = (1. / get_frame_time()).round();
-            self.last_fps_time = get_time();
+        // draw map's "background" layers below sprites
+        for layer in map.level.background_layers.iter() {
+            draw_tile_layer(&map, &camera, layer, time.elapsed_seconds);
         }
-        draw_text(&format!("FPS: {}", self.last_fps), 20.0, 20.0, 32.0, WHITE);
-
-        // draw controls for now
-        draw_text("WASD - movement", 500., 20., 32., WHITE);
-        draw_text("Space - use/interact", 500., 50., 32., WHITE);
-
-        // draw map
-        // TODO: instead of constants for the LEVEL_WIDTH/HEIGHT, and hard-coding tilelayer1/tilelayer2,
-        //       we could extract the concept of a Level and store these things.
-        let level_rect = Rect::new(0., 0., LEVEL_WIDTH - 1., LEVEL_HEIGHT - 1.);
-        let draw_dest_rect = Rect::new(
-            GLOBAL_OFFSET_X,
-            GLOBAL_OFFSET_Y,
-            map.tile_width * map.width * GLOBAL_MULTIPLIER,
-            map.tile_height * map.height * GLOBAL_MULTIPLIER,
-        );
-        map.map.draw_tiles("tilelayer1", draw_dest_rect, level_rect);
-        map.map
-            .draw_tiles("decoration1", draw_dest_rect, level_rect);
 
         // draw any SpriteDrawables with GridPosition
         // TODO: This allocated a new Vec on every frame, and is called out as a bad idea in Specs docs (https://specs.amethyst.rs/docs/tutorials/11_advanced_component.html#sorting-entities-based-on-component-value)
         //       If necessary, we can migrate Positions/Drawables to FlaggedStorage and maintain this sorted set on the System struct
-        let mut to_draw = (&drawables, &positions).join().collect::<Vec<_>>();
+        let mut to_draw = (&drawables, &positions, elevations.maybe())
+            .join()
+            .filter(|(_, position, _)| camera.is_visible(position.x, position.y))
+            .map(|(drawable, position, elevation)| {
+                (drawable, position, Elevation::index_of(elevation))
+            })
+            .collect::<Vec<_>>();
         to_draw.sort_by(|&a, &b| a.1.y.partial_cmp(&b.1.y).unwrap_or(Equal));
-        for (drawable, position) in to_draw {
-            draw_texture_ex(
-                drawable.texture,
-                // x position is simply the current grid position * map tile width (plus the global modifiers)
-                GLOBAL_OFFSET_X + position.x * map.tile_width * GLOBAL_MULTIPLIER,
-                // y position is the current grid position * map tile height, but then we subtract half of
-                // map tile height and sprite height to make it look like the sprite is at bottom of the tile instead of its center
-                GLOBAL_OFFSET_Y + position.y * map.tile_height * GLOBAL_MULTIPLIER
-                    - map.tile_height / 2.
-                    - drawable.tile_height / 2.,
-                WHITE,
-                DrawTextureParams {
-                    source: Some(Rect::new(
-                        drawable.tile_width * drawable.current_frame, // select current animation frame
-                        drawable.tile_height * drawable.row,          // for current texture row
-                        drawable.tile_width,
-                        drawable.tile_height,
-                    )),
-                    dest_size: Some(vec2(
-                        drawable.tile_width * GLOBAL_MULTIPLIER,
-                        drawable.tile_height * GLOBAL_MULTIPLIER,
-                    )),
-                    ..Default::default()
-                },
-            );
+
+        // each elevation's sprites are drawn over everything below them, then covered by that elevation's "foreground" layers to simulate depth
+        let top_elevation = to_draw
+            .iter()
+            .map(|(_, _, elevation)| *elevation)
+            .chain(0..map.level.elevations.len())
+            .max()
+            .unwrap_or(0);
+        for elevation in 0..=top_elevation {
+            for (drawable, position, _) in to_draw.iter().filter(|(_, _, e)| *e == elevation) {
+                draw_sprite(&map, &camera, drawable, position);
+            }
+            if let Some(elevation_conf) = map.level.elevations.get(elevation) {
+                for layer in elevation_conf.foreground_layers.iter() {
+                    draw_tile_layer(&map, &camera, layer, time.elapsed_seconds);
+                }
+            }
         }
 
-        // draw map's "foreground" layer on top of sprites, to simulate depth
-        map.map.draw_tiles("tilelayer2", draw_dest_rect, level_rect);
-        map.map
-            .draw_tiles("decoration2", draw_dest_rect, level_rect);
+        // partially visible tiles and sprites can spill over the edge of the view, so cover that up
+        let view_right = GLOBAL_OFFSET_X + camera.view_width * map.tile_width * GLOBAL_MULTIPLIER;
+        let view_bottom =
+            GLOBAL_OFFSET_Y + camera.view_height * map.tile_height * GLOBAL_MULTIPLIER;
+        draw_rectangle(0., 0., screen_width(), GLOBAL_OFFSET_Y, BLACK);
+        draw_rectangle(0., 0., GLOBAL_OFFSET_X, screen_height(), BLACK);
+        draw_rectangle(
+            view_right,
+            0.,
+            screen_width() - view_right,
+            screen_height(),
+            BLACK,
+        );
+        draw_rectangle(
+            0.,
+            view_bottom,
+            screen_width(),
+            screen_height() - view_bottom,
+            BLACK,
+        );
+
+        // draw FPS
+        if time.elapsed_seconds > self.last_fps_time + 0.2 {
+            self.last_fps = (1. / time.delta_seconds).round();
+            self.last_fps_time = time.elapsed_seconds;
+        }
+        draw_text(&format!("FPS: {}", self.last_fps), 20.0, 20.0, 32.0, WHITE);
+
+        // draw controls for now, with whatever keys they're bound to
+        let localization = &ui_state.localization;
+        let first_key = |action| {
+            bindings
+                .keys(action)
+                .first()
+                .map_or("-", |key| key_name(*key))
+        };
+        let movement_keys = [
+            InputAction::MoveUp,
+            InputAction::MoveLeft,
+            InputAction::MoveDown,
+            InputAction::MoveRight,
+        ]
+        .iter()
+        .map(|action| first_key(*action))
+        .collect::<Vec<_>>()
+        .join("/");
+        let hints = [
+            format!("{} - {}", movement_keys, localization.get("hint.movement")),
+            format!(
+                "{} - {}",
+                bindings.describe(InputAction::Use),
+                localization.get("hint.use")
+            ),
+            format!(
+                "{} - {}",
+                bindings.describe(InputAction::Language),
+                localization.get("hint.language")
+            ),
+            format!(
+                "{}/{} - {} {}",
+                first_key(InputAction::Save),
+                first_key(InputAction::Load),
+                localization.get("hint.save"),
+                save_slots.current_slot + 1
+            ),
+            format!(
+                "{} - {}",
+                bindings.describe(InputAction::Menu),
+                localization.get("hint.controls")
+            ),
+        ];
+        for (index, hint) in hints.iter().enumerate() {
+            draw_text(hint, 500., 20. + index as f32 * 30., 32., WHITE);
+        }
+    }
+}
+
+fn draw_sprite(map: &GameMap, camera: &Camera, drawable: &SpriteDrawable, position: &GridPosition) {
+    // screen position is relative to the camera, using the top-left corner of the entity's tile
+    let (screen_x, screen_y) =
+        camera.grid_to_screen(position.x, position.y, map.tile_width, map.tile_height);
+    draw_texture_ex(
+        drawable.texture,
+        screen_x,
+        // we subtract half of map tile height and sprite height to make it look like the sprite is at bottom of the tile instead of its center
+        screen_y - map.tile_height / 2. - drawable.tile_height / 2.,
+        WHITE,
+        DrawTextureParams {
+            source: Some(Rect::new(
+                drawable.tile_width * drawable.current_frame, // select current animation frame
+                drawable.tile_height * drawable.row,          // for current texture row
+                drawable.tile_width,
+                drawable.tile_height,
+            )),
+            dest_size: Some(vec2(
+                drawable.tile_width * GLOBAL_MULTIPLIER,
+                drawable.tile_height * GLOBAL_MULTIPLIER,
+            )),
+            ..Default::default()
+        },
+    );
+}
+
+// draws only the tiles of the layer that are visible to the camera, as they look at the given time (for animated tiles)
+fn draw_tile_layer(map: &GameMap, camera: &Camera, layer: &str, time: f64) {
+    for y in camera.visible_rows(map.height) {
+        for x in camera.visible_columns(map.width) {
+            if let Some(tile) = map.map.get_tile(layer, x, y) {
+                let (screen_x, screen_y) =
+                    camera.grid_to_screen(x as f32, y as f32, map.tile_width, map.tile_height);
+                map.map.spr(
+                    &tile.tileset,
+                    map.animated_tile_id(&tile.tileset, tile.id, time),
+                    Rect::new(
+                        screen_x,
+                        screen_y,
+                        map.tile_width * GLOBAL_MULTIPLIER,
+                        map.tile_height * GLOBAL_MULTIPLIER,
+                    ),
+                );
+            }
+        }
     }
 }
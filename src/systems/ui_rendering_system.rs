@@ -0,0 +1,22 @@
+use crate::bindings::KeyBindings;
+use crate::ui::UiState;
+
+use specs::ReadExpect;
+use specs::System;
+
+// Draws the open dialog box and controls menu, if any. This is kept apart from UiSystem so that headless simulations can run the UI logic without drawing.
+pub struct UiRenderingSystem;
+
+impl<'a> System<'a> for UiRenderingSystem {
+    type SystemData = (ReadExpect<'a, UiState>, ReadExpect<'a, KeyBindings>);
+
+    fn run(&mut self, data: Self::SystemData) {
+        let (ui_state, bindings) = data;
+        if let Some(dialog_box) = &ui_state.dialog_box {
+            dialog_box.render();
+        }
+        if let Some(controls_menu) = &ui_state.controls_menu {
+            controls_menu.render(&bindings, &ui_state.localization);
+        }
+    }
+}
@@ -1,27 +1,121 @@
+use crate::actions::ActionQueue;
+use crate::bindings::DefaultKeyBindings;
+use crate::bindings::InputAction;
+use crate::bindings::KeyBindings;
+use crate::bindings::MenuRepeat;
+use crate::constants::CONTROLS_FILE;
+use crate::controls_menu::ControlsMenu;
+use crate::controls_menu::MenuOutcome;
+use crate::dialogs::DialogTrees;
+use crate::events::EventQueue;
+use crate::gamepad::GamepadProfiles;
+use crate::platform::Input;
+use crate::platform::Time;
+use crate::recording::ReplayState;
 use crate::ui::UiState;
 
-use macroquad::prelude::is_key_pressed;
-use macroquad::prelude::KeyCode;
-
+use specs::ReadExpect;
 use specs::System;
 use specs::WriteExpect;
 
-pub struct UiSystem;
+#[derive(Default)]
+pub struct UiSystem {
+    pub menu_repeat: MenuRepeat, // for holding a direction to move through dialog choices
+}
 
 impl<'a> System<'a> for UiSystem {
-    type SystemData = (WriteExpect<'a, UiState>,);
+    #[allow(clippy::type_complexity)]
+    type SystemData = (
+        WriteExpect<'a, UiState>,
+        WriteExpect<'a, EventQueue>,
+        WriteExpect<'a, ActionQueue>,
+        ReadExpect<'a, DialogTrees>,
+        ReadExpect<'a, Time>,
+        ReadExpect<'a, Input>,
+        WriteExpect<'a, KeyBindings>,
+        ReadExpect<'a, DefaultKeyBindings>,
+        ReadExpect<'a, GamepadProfiles>,
+        ReadExpect<'a, ReplayState>,
+    );
 
     fn run(&mut self, data: Self::SystemData) {
-        let (mut ui_state,) = data;
+        let (
+            mut ui_state,
+            mut event_queue,
+            mut action_queue,
+            dialog_trees,
+            time,
+            input,
+            mut bindings,
+            default_bindings,
+            gamepad_profiles,
+            replay_state,
+        ) = data;
+
+        // the controls menu takes all the input while it's open, so keys can be rebound to anything
+        if let Some(controls_menu) = ui_state.controls_menu.as_mut() {
+            let outcome = controls_menu.update(
+                &input,
+                time.delta_seconds,
+                &mut bindings,
+                &default_bindings.bindings,
+                &gamepad_profiles,
+            );
+            match outcome {
+                MenuOutcome::Open => {}
+                // a replay changes the controls for this session only, the config file stays as the player left it
+                MenuOutcome::Changed if replay_state.replaying => {}
+                MenuOutcome::Changed => {
+                    if let Err(err) = bindings.save() {
+                        println!(
+                            "WARNING: failed to save controls to {}: {}",
+                            CONTROLS_FILE, err
+                        );
+                    }
+                }
+                MenuOutcome::Closed => ui_state.controls_menu = None,
+            }
+            return;
+        }
 
-        if let Some(dialog_box) = &ui_state.dialog_box {
-            // Render the existing dialog
-            dialog_box.render();
+        // language can be switched at any time, including while a dialog is open
+        if bindings.is_pressed(&input, InputAction::Language) {
+            ui_state.cycle_language();
+        }
+
+        ui_state.dialog_update(time.delta_seconds);
+
+        if ui_state.dialog_box.is_some() {
+            // Handle input to move between choices (only does anything when choices are showing)
+            let delta_seconds = time.delta_seconds;
+            if self
+                .menu_repeat
+                .is_triggered(&bindings, &input, InputAction::MoveUp, delta_seconds)
+            {
+                ui_state.dialog_move_choice_cursor(true);
+            }
+            if self.menu_repeat.is_triggered(
+                &bindings,
+                &input,
+                InputAction::MoveDown,
+                delta_seconds,
+            ) {
+                ui_state.dialog_move_choice_cursor(false);
+            }
 
-            // Handle input to advance pages
-            if is_key_pressed(KeyCode::Space) {
-                ui_state.dialog_next_page();
+            // Handle input to finish revealing the page or advance pages, which confirms the selected choice on the last page.
+            // clicking (or tapping) anywhere does the same, so the game can be played on a touch screen
+            if bindings.is_pressed(&input, InputAction::Use) || input.click().is_some() {
+                if let Some(choice_event) =
+                    ui_state.dialog_continue(&dialog_trees, &mut action_queue)
+                {
+                    event_queue.new_events.push(choice_event);
+                }
             }
+        } else if bindings.is_pressed(&input, InputAction::Menu) {
+            ui_state.controls_menu = Some(ControlsMenu {
+                ..Default::default()
+            });
         }
     }
 }
@@ -1,52 +1,132 @@
 use crate::FontAtlas;
 
+const MISSING_GLYPH_PLACEHOLDER: char = '?';
+
+// Wraps text and splits it into chunks (pages) of at most lines_per_chunk lines.
+// Paragraphs (separated by blank lines) always start on a new chunk.
 pub fn chunk_text(
     text: &str,
     wrap_width: f32,
     lines_per_chunk: usize,
     font_atlas: &FontAtlas,
 ) -> Vec<String> {
-    let wrapped_text = wrap_text(text, wrap_width, font_atlas);
-    let lines: Vec<&str> = wrapped_text.split('\n').collect();
-    lines
-        .chunks(lines_per_chunk)
-        .map(|f| f.join("\n"))
-        .collect()
+    let mut chunks = vec![];
+    for paragraph in split_paragraphs(text) {
+        let wrapped_text = wrap_text(&paragraph, wrap_width, font_atlas);
+        let lines: Vec<&str> = wrapped_text.split('\n').collect();
+        chunks.extend(lines.chunks(lines_per_chunk).map(|f| f.join("\n")));
+    }
+    // there's always at least one chunk, even if it's empty
+    if chunks.is_empty() {
+        chunks.push(String::new());
+    }
+    chunks
 }
 
+// Wraps text to fit within wrap_width, by replacing spaces with newlines.
+// Explicit newlines in the text are kept as line breaks, and runs of spaces are collapsed.
+// Words that are wider than wrap_width on their own are broken up at whichever character would overflow.
+// Note that only whitespace is ever added or removed, which markup.rs relies on.
 pub fn wrap_text(text: &str, wrap_width: f32, font_atlas: &FontAtlas) -> String {
-    let words = text.split(' ');
     let space_width = character_width(font_atlas, ' ');
     let mut lines = vec![];
-    let mut current_line = vec![];
-    let mut current_pixels = 0.;
-    for word in words {
-        let word_width = word_width(font_atlas, word);
-        if word_width + current_pixels + (current_line.len() as f32 * space_width) > wrap_width {
-            lines.push(current_line.join(" "));
-            current_line = vec![];
-            current_pixels = word_width;
-            current_line.push(word);
-        } else {
+    for source_line in text.split('\n') {
+        let mut current_line = vec![];
+        let mut current_pixels = 0.;
+        for word in source_line.split_whitespace() {
+            let word_width = word_width(font_atlas, word);
+            if word_width > wrap_width {
+                if !current_line.is_empty() {
+                    lines.push(current_line.join(" "));
+                    current_line = vec![];
+                }
+                let mut pieces = break_word(word, wrap_width, font_atlas);
+                // the last piece may have room for more words after it, so it starts the next line
+                let last_piece = pieces.pop().unwrap_or_default();
+                lines.extend(pieces.iter().map(|piece| piece.to_string()));
+                current_pixels = self::word_width(font_atlas, last_piece);
+                current_line.push(last_piece);
+                continue;
+            }
+            if !current_line.is_empty()
+                && word_width + current_pixels + (current_line.len() as f32 * space_width)
+                    > wrap_width
+            {
+                lines.push(current_line.join(" "));
+                current_line = vec![];
+                current_pixels = 0.;
+            }
             current_pixels += word_width;
             current_line.push(word);
         }
-    }
-    if !current_line.is_empty() {
-        lines.push(current_line.join(" "))
+        lines.push(current_line.join(" "));
     }
     lines.join("\n")
 }
 
-fn character_width(font_atlas: &FontAtlas, character: char) -> f32 {
+// splits text on blank lines, dropping the blank lines themselves
+fn split_paragraphs(text: &str) -> Vec<String> {
+    let mut paragraphs = vec![];
+    let mut current_paragraph = vec![];
+    for line in text.split('\n') {
+        if line.trim().is_empty() {
+            if !current_paragraph.is_empty() {
+                paragraphs.push(current_paragraph.join("\n"));
+                current_paragraph = vec![];
+            }
+        } else {
+            current_paragraph.push(line);
+        }
+    }
+    if !current_paragraph.is_empty() {
+        paragraphs.push(current_paragraph.join("\n"));
+    }
+    paragraphs
+}
+
+// breaks a single word into pieces that each fit within wrap_width (at least one character per piece)
+fn break_word<'a>(word: &'a str, wrap_width: f32, font_atlas: &FontAtlas) -> Vec<&'a str> {
+    let mut pieces = vec![];
+    let mut piece_start = 0;
+    let mut piece_pixels = 0.;
+    for (index, character) in word.char_indices() {
+        let width = character_width(font_atlas, character);
+        if index > piece_start && piece_pixels + width > wrap_width {
+            pieces.push(&word[piece_start..index]);
+            piece_start = index;
+            piece_pixels = 0.;
+        }
+        piece_pixels += width;
+    }
+    pieces.push(&word[piece_start..]);
+    pieces
+}
+
+pub fn character_width(font_atlas: &FontAtlas, character: char) -> f32 {
     if let Some(font_data) = font_atlas.character_infos.get(&character) {
         let font_data = font_data.scale(font_atlas.font_size as f32);
         let width = font_data.left_padding + font_data.size.0 + font_data.right_padding;
         return width;
     }
+    // glyph is missing from the atlas (see missing_glyphs), so measure it like a placeholder
+    // instead of as 0 width, which would make the line wrap too late
+    if character != MISSING_GLYPH_PLACEHOLDER {
+        return character_width(font_atlas, MISSING_GLYPH_PLACEHOLDER);
+    }
     0.
 }
 
+// returns the characters in text that have no glyph in the font atlas, so they can be reported
+pub fn missing_glyphs(text: &str, font_atlas: &FontAtlas) -> Vec<char> {
+    let mut missing: Vec<char> = text
+        .chars()
+        .filter(|c| !c.is_whitespace() && !font_atlas.character_infos.contains_key(c))
+        .collect();
+    missing.sort_unstable();
+    missing.dedup();
+    missing
+}
+
 fn word_width(font_atlas: &FontAtlas, word: &str) -> f32 {
     word.chars()
         .map(|c| character_width(font_atlas, c))
@@ -78,6 +158,17 @@ mod tests {
         // space is slightly different width
         let expected_width = 10.769232;
         assert_eq!(character_width(&font_atlas(), ' '), expected_width);
+        // characters missing from the atlas are measured like the placeholder
+        assert_eq!(
+            character_width(&font_atlas(), '漢'),
+            character_width(&font_atlas(), '?')
+        );
+    }
+
+    #[test]
+    fn test_missing_glyphs() {
+        assert!(missing_glyphs("Hello there!", &font_atlas()).is_empty());
+        assert_eq!(missing_glyphs("naïve café", &font_atlas()), vec!['é', 'ï']);
     }
 
     #[test]
@@ -100,6 +191,58 @@ mod tests {
             "Here is\nsome long\ntext that\nshould go\non to the\nnext\nline. You\nsee, this\ngame is\nstarting\nto get\nsome\nstory.");
     }
 
+    #[test]
+    fn test_wrap_long_words() {
+        // 9 characters fit in 100px, 10 don't
+        assert_eq!(
+            wrap_text("abcdefghijklmnopqrstuvwxyz", 100., &font_atlas()),
+            "abcdefghi\njklmnopqr\nstuvwxyz"
+        );
+        // no empty line when the very first word is too long
+        assert_eq!(
+            wrap_text("abcdefghijkl", 100., &font_atlas()),
+            "abcdefghi\njkl"
+        );
+        // the end of a broken word can share a line with the next word
+        assert_eq!(
+            wrap_text("Hi abcdefghijklm no", 100., &font_atlas()),
+            "Hi\nabcdefghi\njklm no"
+        );
+    }
+
+    #[test]
+    fn test_wrap_explicit_newlines() {
+        assert_eq!(
+            wrap_text("Hello\nthere", 600., &font_atlas()),
+            "Hello\nthere"
+        );
+        assert_eq!(
+            wrap_text(
+                "Here is some long\ntext that should wrap.",
+                100.,
+                &font_atlas()
+            ),
+            "Here is\nsome long\ntext that\nshould\nwrap."
+        );
+    }
+
+    #[test]
+    fn test_wrap_collapses_spaces() {
+        assert_eq!(
+            wrap_text("  Hello    there  ", 600., &font_atlas()),
+            "Hello there"
+        );
+    }
+
+    #[test]
+    fn test_chunk_text_paragraphs() {
+        assert_eq!(
+            chunk_text("One two\n\nThree\n\n\nFour\nfive", 600., 3, &font_atlas()),
+            vec!["One two", "Three", "Four\nfive"]
+        );
+        assert_eq!(chunk_text("", 600., 3, &font_atlas()), vec![""]);
+    }
+
     #[test]
     fn test_chunk_text() {
         assert_eq!(chunk_text("Here is some long text that should go on to the next line. You see, this game is starting to get some story.", 100., 3, &font_atlas()), 
@@ -1,9 +1,23 @@
 use crate::FONT_SIZE;
 use crate::constants::DIALOG_BOX_HEIGHT;
+use crate::constants::DIALOG_CHOICE_HEIGHT;
 use crate::constants::DIALOG_BOX_WIDTH;
+use crate::constants::DIALOG_CHARS_PER_SECOND;
+use crate::constants::DIALOG_PORTRAIT_SIZE;
+use crate::constants::DIALOG_TEXT_MARGIN;
 use crate::constants::UI_TEXTURE_CARET;
+use crate::constants::UI_TEXTURE_PORTRAIT;
+use crate::actions::ActionQueue;
+use crate::controls_menu::ControlsMenu;
+use crate::dialogs::DialogTrees;
+use crate::events::Event;
+use crate::localization::Localization;
+use crate::markup::parse_markup;
+use crate::markup::MarkupText;
 use crate::megaui::widgets::Texture;
+use crate::text::character_width;
 use crate::text::chunk_text;
+use crate::text::missing_glyphs;
 use crate::FontAtlas;
 use macroquad::prelude::glam;
 use macroquad::prelude::vec2;
@@ -12,38 +26,87 @@ use macroquad::prelude::Vec2;
 use megaui_macroquad::draw_window;
 use megaui_macroquad::megaui::hash;
 use megaui_macroquad::megaui::widgets::Label;
+use megaui_macroquad::megaui::Color;
 use megaui_macroquad::megaui::Vector2;
+use megaui_macroquad::set_megaui_texture;
 use megaui_macroquad::WindowParams;
+use serde::Deserialize;
+use serde::Serialize;
 use std::hash::Hash;
 use std::hash::Hasher;
 
 pub struct UiState {
     pub font_atlas: FontAtlas,
+    pub localization: Localization,
+    pub player_name: String, // substituted for [player] tags in dialog text
     pub dialog_box: Option<DialogBox>,
+    pub conversation_node: Option<String>, // id of the dialog tree node being shown, if the dialog box is part of a conversation
+    pub controls_menu: Option<ControlsMenu>,
 }
 
-#[derive(Debug, Clone, Default)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
 pub struct DialogBoxConf {
-    pub message: String,
+    // message, title and choices are string ids, looked up in the current language's string table
+    pub id: u32, // identifies this dialog in DialogChoiceSelected events, only needed when there are choices
+    pub message: String, // may contain markup tags, see markup.rs
     pub title: Option<String>,
+    #[serde(skip)]
     pub portrait: Option<Texture2D>,
+    pub portrait_path: Option<String>, // where the portrait texture was loaded from, so saves can load it again
     pub choices: Vec<String>,
+    pub chars_per_second: Option<f32>, // how fast text is revealed, defaults to DIALOG_CHARS_PER_SECOND
 }
 
 #[derive(Default, Debug)]
 pub struct DialogBox {
+    conf: DialogBoxConf, // kept so the dialog box can be rebuilt if the language changes
+    id: u32,
     position: Vec2,
-    text_pages: Vec<String>,
+    pages: Vec<DialogPage>,
     current_page: usize,
     title: Option<String>,
     portrait: Option<Texture2D>,
     choices: Vec<String>,
+    selected_choice: usize,
+    chars_per_second: f32,
+    revealed_chars: f32, // how many characters of the current page are showing so far
+    next_pause: usize,   // index into the current page's pauses
+    pause_remaining: f32, // seconds left before the reveal continues
+}
+
+// Everything needed to bring back an open dialog box, e.g. from a save file
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DialogBoxState {
+    pub conf: DialogBoxConf,
+    pub conversation_node: Option<String>,
+    pub current_page: usize,
+    pub selected_choice: usize,
+}
+
+// A page of dialog text, already wrapped and laid out into runs of the same color
+#[derive(Default, Debug)]
+struct DialogPage {
+    runs: Vec<TextRun>,
+    line_count: usize,
+    char_count: usize,
+    pauses: Vec<(usize, f32)>, // (character index within page, seconds)
+}
+
+#[derive(Debug)]
+struct TextRun {
+    text: String,
+    color: Color,
+    x: f32, // offset from the start of the line
+    line: usize,
+    first_char: usize, // character index within page, used to figure out how much of the run is revealed
 }
 
 impl Hash for DialogBox {
     fn hash<H: Hasher>(&self, state: &mut H) {
         // hasher must include any elements of self that will change the WindowParams passed to draw_window
         self.title.is_some().hash(state);
+        self.is_showing_choices().hash(state);
     }
 }
 
@@ -58,49 +121,162 @@ impl DialogBox {
             window_params.label = title.to_owned();
             window_params.titlebar = true;
         }
+        // the box grows downward to make room for the list of choices
+        let mut height = DIALOG_BOX_HEIGHT;
+        if self.is_showing_choices() {
+            height += self.choices.len() as f32 * DIALOG_CHOICE_HEIGHT;
+        }
         draw_window(
             hash!(self),
             self.position,
-            glam::vec2(DIALOG_BOX_WIDTH, DIALOG_BOX_HEIGHT),
+            glam::vec2(DIALOG_BOX_WIDTH, height),
             window_params,
             |ui| {
-                Label::new(&self.text_pages[self.current_page])
-                    .multiline(FONT_SIZE as f32)
-                    .ui(ui);
+                // portrait goes in its own column on the left, pushing the text over to the right of it
+                let mut text_x = 0.;
+                if self.portrait.is_some() {
+                    Texture::new(UI_TEXTURE_PORTRAIT)
+                        .size(DIALOG_PORTRAIT_SIZE, DIALOG_PORTRAIT_SIZE)
+                        .position(Some(Vector2::new(0., 0.)))
+                        .ui(ui);
+                    text_x = DIALOG_PORTRAIT_SIZE + DIALOG_TEXT_MARGIN;
+                }
 
-                // TODO: Portrait. Make this with Texture like below caret
+                // draw each colored run of text manually, since Label only supports a single color
+                let page = &self.pages[self.current_page];
+                let revealed_chars = self.revealed_chars as usize;
+                {
+                    let mut canvas = ui.canvas();
+                    let origin = canvas.cursor();
+                    for run in page.runs.iter() {
+                        if run.first_char >= revealed_chars {
+                            break;
+                        }
+                        let revealed_text: String = run
+                            .text
+                            .chars()
+                            .take(revealed_chars - run.first_char)
+                            .collect();
+                        canvas.draw_text(
+                            &revealed_text,
+                            Vector2::new(
+                                origin.x + text_x + run.x,
+                                origin.y + run.line as f32 * FONT_SIZE as f32,
+                            ),
+                            run.color,
+                        );
+                    }
+                }
 
-                
+                // nothing else shows up until the whole page has been revealed
+                if !self.is_page_revealed() {
+                    return;
+                }
 
                 // if showing any page but last page, show continue caret
-                if self.current_page < self.text_pages.len() - 1 {
+                if self.current_page < self.pages.len() - 1 {
                     Texture::new(UI_TEXTURE_CARET)
                         .size(22., 22.)
                         // bottom-right corner
                         .position(Some(Vector2::new(DIALOG_BOX_WIDTH - 32., DIALOG_BOX_HEIGHT - 32.)))
                         .ui(ui);
                 } else {
-                    // TODO: choices, show on last page
+                    // on the last page, show the choices below the text with a cursor next to the selected one
+                    let choices_y = page.line_count as f32 * FONT_SIZE as f32;
+                    for (index, choice) in self.choices.iter().enumerate() {
+                        let cursor = if index == self.selected_choice {
+                            ">"
+                        } else {
+                            " "
+                        };
+                        Label::new(&format!("{} {}", cursor, choice))
+                            .position(Some(Vector2::new(
+                                text_x,
+                                choices_y + index as f32 * DIALOG_CHOICE_HEIGHT,
+                            )))
+                            .ui(ui);
+                    }
                 }
-                
             },
         );
     }
     // advances to next page of text and returns true.
     // returns false if there is no next page
     pub fn advance_to_next_page(&mut self) -> bool {
-        if self.current_page + 1 < self.text_pages.len() {
+        if self.current_page + 1 < self.pages.len() {
             self.current_page += 1;
+            self.revealed_chars = 0.;
+            self.next_pause = 0;
+            self.pause_remaining = 0.;
             return true;
         }
         false
     }
+
+    // reveals more characters of the current page, based on how much time has passed
+    pub fn update(&mut self, delta_time: f32) {
+        if self.is_page_revealed() {
+            return;
+        }
+        if self.pause_remaining > 0. {
+            self.pause_remaining -= delta_time;
+            return;
+        }
+        self.revealed_chars += delta_time * self.chars_per_second;
+        // stop at the next [pause] tag, if we just reached it
+        let page = &self.pages[self.current_page];
+        if let Some(&(pause_at, seconds)) = page.pauses.get(self.next_pause) {
+            if self.revealed_chars as usize >= pause_at {
+                self.revealed_chars = pause_at as f32;
+                self.pause_remaining = seconds;
+                self.next_pause += 1;
+            }
+        }
+    }
+
+    pub fn is_page_revealed(&self) -> bool {
+        let page = &self.pages[self.current_page];
+        // a pause at the very end of the page has to run out too
+        self.revealed_chars as usize >= page.char_count
+            && self.next_pause >= page.pauses.len()
+            && self.pause_remaining <= 0.
+    }
+
+    // skips to the end of the current page's reveal, including any pauses
+    pub fn reveal_page(&mut self) {
+        let page = &self.pages[self.current_page];
+        self.revealed_chars = page.char_count as f32;
+        self.next_pause = page.pauses.len();
+        self.pause_remaining = 0.;
+    }
+
+    fn is_on_last_page(&self) -> bool {
+        self.current_page + 1 >= self.pages.len()
+    }
+
+    fn is_showing_choices(&self) -> bool {
+        self.is_on_last_page() && !self.choices.is_empty()
+    }
+
+    // moves the choice cursor up or down, wrapping around at either end.
+    // does nothing unless the choices are currently showing.
+    pub fn move_choice_cursor(&mut self, up: bool) {
+        if !self.is_showing_choices() || !self.is_page_revealed() {
+            return;
+        }
+        let num_choices = self.choices.len();
+        if up {
+            self.selected_choice = (self.selected_choice + num_choices - 1) % num_choices;
+        } else {
+            self.selected_choice = (self.selected_choice + 1) % num_choices;
+        }
+    }
 }
 
 impl UiState {
     // Returns true if the UI is currently engaged, meaning that the typical player inputs should be directed toward the UI as opposed to the game world.
     pub fn is_engaged(&self) -> bool {
-        self.dialog_box.is_some()
+        self.dialog_box.is_some() || self.controls_menu.is_some()
     }
 
     // create dialog box entity
@@ -109,31 +285,307 @@ impl UiState {
     // - based on the above, create the appropriate window layout.
     // for now I'm going to use megaUI for this, but in the future might need manual draw_text / draw_rect, in order to create transitions I want
     pub fn create_dialog_box(&mut self, conf: DialogBoxConf) {
-        let mut lines_per_page = 3;
-        if conf.title.is_some() {
-            lines_per_page = 2;
+        self.dialog_box = Some(self.build_dialog_box(conf));
+        self.conversation_node = None;
+    }
+
+    fn build_dialog_box(&self, conf: DialogBoxConf) -> DialogBox {
+        let (wrap_width, lines_per_page) =
+            dialog_text_layout(conf.title.is_some(), conf.portrait.is_some());
+        if let Some(portrait) = conf.portrait {
+            set_megaui_texture(UI_TEXTURE_PORTRAIT, portrait);
+        }
+        let message = self.localization.get(&conf.message);
+        let markup = parse_markup(&message, &self.player_name);
+        let missing = missing_glyphs(&markup.text, &self.font_atlas);
+        if !missing.is_empty() {
+            println!(
+                "WARNING: font atlas is missing glyphs {:?} used in dialog: {}",
+                missing, markup.text
+            );
         }
         let mut dialog_box = DialogBox {
+            id: conf.id,
             position: vec2(10., 500.),
-            text_pages: chunk_text(&conf.message, 760., lines_per_page, &self.font_atlas),
-            title: conf.title,
+            pages: layout_pages(&markup, wrap_width, lines_per_page, &self.font_atlas),
+            title: conf
+                .title
+                .as_ref()
+                .map(|title| self.localization.get(title)),
             portrait: conf.portrait,
+            chars_per_second: conf.chars_per_second.unwrap_or(DIALOG_CHARS_PER_SECOND),
             ..Default::default()
         };
 
         if !conf.choices.is_empty() {
-            dialog_box.choices = conf.choices.to_vec();
+            // choices are drawn as plain labels, so any markup is just stripped out
+            dialog_box.choices = conf
+                .choices
+                .iter()
+                .map(|choice| parse_markup(&self.localization.get(choice), &self.player_name).text)
+                .collect();
         }
 
-        self.dialog_box = Some(dialog_box);
+        dialog_box.conf = conf;
+        dialog_box
     }
 
-    pub fn dialog_next_page(&mut self) {
+    // Switches to the next language, re-chunking any open dialog box in the new language.
+    // The dialog box stays on the same page (if the new text has that many) with the same choice selected.
+    pub fn cycle_language(&mut self) {
+        self.localization.cycle_language();
+        if let Some(old_dialog_box) = self.dialog_box.take() {
+            let mut dialog_box = self.build_dialog_box(old_dialog_box.conf);
+            dialog_box.current_page = old_dialog_box.current_page.min(dialog_box.pages.len() - 1);
+            dialog_box.selected_choice = old_dialog_box.selected_choice;
+            dialog_box.reveal_page();
+            self.dialog_box = Some(dialog_box);
+        }
+    }
+
+    // Shows the dialog box for the given dialog tree node, so the player can walk through the conversation from there
+    pub fn start_conversation(&mut self, node_id: &str, dialog_trees: &DialogTrees) {
+        match dialog_trees.nodes.get(node_id) {
+            Some(node) => {
+                self.create_dialog_box(DialogBoxConf {
+                    message: node.text.to_owned(),
+                    title: node.speaker.to_owned(),
+                    portrait: node
+                        .portrait
+                        .as_ref()
+                        .and_then(|portrait| dialog_trees.portraits.get(portrait).copied()),
+                    portrait_path: node.portrait.to_owned(),
+                    choices: node
+                        .choices
+                        .iter()
+                        .map(|choice| choice.text.to_owned())
+                        .collect(),
+                    ..Default::default()
+                });
+                self.conversation_node = Some(node_id.to_owned());
+            }
+            None => println!(
+                "WARNING: tried to start conversation at unknown node {}",
+                node_id
+            ),
+        }
+    }
+
+    pub fn dialog_update(&mut self, delta_time: f32) {
+        if let Some(dialog_box) = &mut self.dialog_box {
+            dialog_box.update(delta_time);
+        }
+    }
+
+    // Handles the player pressing the "continue" button: the first press finishes revealing the current page,
+    // and a press on an already revealed page advances to the next page (see dialog_next_page)
+    pub fn dialog_continue(
+        &mut self,
+        dialog_trees: &DialogTrees,
+        action_queue: &mut ActionQueue,
+    ) -> Option<Event> {
+        if let Some(dialog_box) = &mut self.dialog_box {
+            if !dialog_box.is_page_revealed() {
+                dialog_box.reveal_page();
+                return None;
+            }
+        }
+        self.dialog_next_page(dialog_trees, action_queue)
+    }
+
+    // Advances the dialog box to its next page, closing it after the last page.
+    // If the dialog box was part of a conversation, closing it moves on to the next node (queueing up any of its actions).
+    // Otherwise, returns a DialogChoiceSelected event if closing the dialog box confirmed one of its choices.
+    pub fn dialog_next_page(
+        &mut self,
+        dialog_trees: &DialogTrees,
+        action_queue: &mut ActionQueue,
+    ) -> Option<Event> {
         if let Some(dialog_box) = &mut self.dialog_box {
             // advancing beyond the last page maens we should close the dialog box, by removing it from self
             if !dialog_box.advance_to_next_page() {
+                let mut selected_choice = None;
+                if !dialog_box.choices.is_empty() {
+                    selected_choice = Some(dialog_box.selected_choice);
+                }
+                let dialog_id = dialog_box.id;
                 self.dialog_box = None;
+
+                if let Some(node_id) = self.conversation_node.take() {
+                    self.continue_conversation(
+                        &node_id,
+                        selected_choice,
+                        dialog_trees,
+                        action_queue,
+                    );
+                    return None;
+                }
+                return selected_choice
+                    .map(|choice| Event::DialogChoiceSelected(dialog_id, choice));
             }
         }
+        None
+    }
+
+    // follows the chosen link out of a finished conversation node (or the node's own link if it had no choices)
+    fn continue_conversation(
+        &mut self,
+        node_id: &str,
+        selected_choice: Option<usize>,
+        dialog_trees: &DialogTrees,
+        action_queue: &mut ActionQueue,
+    ) {
+        if let Some(node) = dialog_trees.nodes.get(node_id) {
+            let (next, actions) = match selected_choice.and_then(|choice| node.choices.get(choice))
+            {
+                Some(choice) => (&choice.next, &choice.actions),
+                None => (&node.next, &node.actions),
+            };
+            action_queue.actions.extend(actions.iter().cloned());
+            if let Some(next) = next {
+                self.start_conversation(next, dialog_trees);
+            }
+        }
+    }
+
+    pub fn dialog_box_state(&self) -> Option<DialogBoxState> {
+        self.dialog_box.as_ref().map(|dialog_box| DialogBoxState {
+            conf: dialog_box.conf.clone(),
+            conversation_node: self.conversation_node.clone(),
+            current_page: dialog_box.current_page,
+            selected_choice: dialog_box.selected_choice,
+        })
+    }
+
+    // Replaces any open dialog box with the given one (or none), with the page fully revealed.
+    // Conversations are restarted from their dialog tree node, so they pick up the node's portrait again.
+    pub fn restore_dialog_box(
+        &mut self,
+        state: Option<DialogBoxState>,
+        dialog_trees: &DialogTrees,
+    ) {
+        self.dialog_box = None;
+        self.conversation_node = None;
+        let state = match state {
+            Some(state) => state,
+            None => return,
+        };
+        match &state.conversation_node {
+            Some(node_id) => self.start_conversation(node_id, dialog_trees),
+            None => self.create_dialog_box(state.conf),
+        }
+        if let Some(dialog_box) = &mut self.dialog_box {
+            dialog_box.current_page = state.current_page.min(dialog_box.pages.len() - 1);
+            dialog_box.selected_choice = state.selected_choice;
+            dialog_box.reveal_page();
+        }
+    }
+
+    pub fn dialog_move_choice_cursor(&mut self, up: bool) {
+        if let Some(dialog_box) = &mut self.dialog_box {
+            dialog_box.move_choice_cursor(up);
+        }
+    }
+}
+
+// Returns the (wrap width, lines per page) available for text inside a dialog box.
+// The title bar takes up a line of height, and the portrait takes up a column of width on the left.
+fn dialog_text_layout(has_title: bool, has_portrait: bool) -> (f32, usize) {
+    let mut wrap_width = DIALOG_BOX_WIDTH - 2. * DIALOG_TEXT_MARGIN;
+    if has_portrait {
+        wrap_width -= DIALOG_PORTRAIT_SIZE + DIALOG_TEXT_MARGIN;
+    }
+    let mut lines_per_page = 3;
+    if has_title {
+        lines_per_page = 2;
+    }
+    (wrap_width, lines_per_page)
+}
+
+// Wraps and chunks the markup's plain text into pages, then splits each line into runs of the same color.
+// Whitespace is never styled, so it just joins whichever run it's in.
+fn layout_pages(
+    markup: &MarkupText,
+    wrap_width: f32,
+    lines_per_page: usize,
+    font_atlas: &FontAtlas,
+) -> Vec<DialogPage> {
+    // matches the focused_text color in the UI style set up in main.rs
+    let default_color = Color::from_rgb(255, 255, 255);
+    let mut visible_index = 0;
+    let mut pages = vec![];
+    for page_text in chunk_text(&markup.text, wrap_width, lines_per_page, font_atlas) {
+        let mut page = DialogPage {
+            ..Default::default()
+        };
+        for (line_index, line) in page_text.split('\n').enumerate() {
+            if line_index > 0 {
+                page.char_count += 1; // the line break takes a "character" of time to reveal
+            }
+            page.line_count += 1;
+            let mut x = 0.;
+            for character in line.chars() {
+                let mut color = default_color;
+                if !character.is_whitespace() {
+                    if let Some(pause) =
+                        markup.pauses.iter().find(|pause| pause.at == visible_index)
+                    {
+                        page.pauses.push((page.char_count, pause.seconds));
+                    }
+                    color = markup.color_at(visible_index).unwrap_or(default_color);
+                    visible_index += 1;
+                }
+                match page.runs.last_mut() {
+                    Some(run)
+                        if run.line == line_index
+                            && (character.is_whitespace() || run.color == color) =>
+                    {
+                        run.text.push(character)
+                    }
+                    _ => page.runs.push(TextRun {
+                        text: character.to_string(),
+                        color,
+                        x,
+                        line: line_index,
+                        first_char: page.char_count,
+                    }),
+                }
+                x += character_width(font_atlas, character);
+                page.char_count += 1;
+            }
+        }
+        pages.push(page);
+    }
+    // a pause after the last character (e.g. before the choices show up) has no character to wait in front of
+    if let Some(pause) = markup.pauses.iter().find(|pause| pause.at == visible_index) {
+        if let Some(page) = pages.last_mut() {
+            page.pauses.push((page.char_count, pause.seconds));
+        }
+    }
+    pages
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn font_atlas() -> FontAtlas {
+        FontAtlas::new(
+            &include_bytes!("../../megaui/assets/ProggyClean.ttf")[..],
+            20,
+            FontAtlas::ascii_character_list(),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_layout_pauses() {
+        let markup = parse_markup("Wait...[pause=1.5] for it.[pause=2]", "Rob");
+        let pages = layout_pages(&markup, 600., 3, &font_atlas());
+        assert_eq!(pages.len(), 1);
+        assert_eq!(pages[0].char_count, 15);
+        // the pause at the end is kept, after the last character
+        assert_eq!(pages[0].pauses, vec![(8, 1.5), (15, 2.)]);
     }
 }